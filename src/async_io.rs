@@ -0,0 +1,293 @@
+// Async counterparts of Compressor and Decompressor, for tokio's io traits
+// (the `tokio` feature) and for the futures crate's (the `futures` feature).
+// They share the chunk encoding and decoding of the blocking types, and differ
+// only in how they move bytes to and from the inner stream.
+
+use std::boxed::Box;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+use std::vec::Vec;
+
+use crate::definitions::*;
+use crate::compress::{ChunkEncoder, CompressionPolicy};
+use crate::decompress::ChunkDecoder;
+
+// AsyncCompressor writes the framing format to an async writer. Like the
+// Compressor, it stages input until a full chunk is available; a partial chunk
+// is only emitted on flush or shutdown. There is no Drop to fall back on, so
+// shut the AsyncCompressor down (or close it) to end the stream.
+pub struct AsyncCompressor<W> {
+	inner: W,
+	// buf_src holds input that has not been encoded as a chunk yet.
+	buf_src: Vec<u8>,
+	chunks: ChunkEncoder,
+	// buf_out[pos:] holds encoded output that has not been written yet.
+	buf_out: Vec<u8>,
+	pos: usize,
+	wrote_header: bool,
+}
+
+impl <W> AsyncCompressor<W> {
+
+	pub fn new(inner: W) -> AsyncCompressor<W> {
+		AsyncCompressor::with_policy(inner, CompressionPolicy::default())
+	}
+
+	// Creates an AsyncCompressor which applies policy to decide which chunks
+	// to store compressed.
+	pub fn with_policy(inner: W, policy: CompressionPolicy) -> AsyncCompressor<W> {
+		AsyncCompressor {
+			inner,
+			buf_src: Vec::with_capacity(MAX_UNCOMPRESSED_CHUNK_LEN as usize),
+			chunks: ChunkEncoder::new(policy),
+			buf_out: Vec::new(),
+			pos: 0,
+			wrote_header: false,
+		}
+	}
+
+	// Gets a reference to the inner writer.
+	pub fn get_ref(&self) -> &W {
+		&self.inner
+	}
+
+	// Gets a mutable reference to the inner writer. Writing to it directly
+	// will corrupt the framed stream.
+	pub fn get_mut(&mut self) -> &mut W {
+		&mut self.inner
+	}
+
+	// Returns the inner writer. Any input that has not been flushed is lost.
+	pub fn into_inner(self) -> W {
+		self.inner
+	}
+
+	// Stages what fits of src into the current chunk, and encodes the chunk
+	// once it is full. Returns how much of src was taken.
+	fn stage(&mut self, src: &[u8]) -> io::Result<usize> {
+		let chunk_len = MAX_UNCOMPRESSED_CHUNK_LEN as usize;
+		let n = ::std::cmp::min(chunk_len - self.buf_src.len(), src.len());
+		self.buf_src.extend_from_slice(&src[..n]);
+		if self.buf_src.len() == chunk_len {
+			self.encode_staged()?;
+		}
+		Ok(n)
+	}
+
+	// Encodes any staged input as a chunk into buf_out, which must have been
+	// written out, preceded by the stream identifier if it is the first.
+	fn encode_staged(&mut self) -> io::Result<()> {
+		if self.buf_src.is_empty() {
+			return Ok(());
+		}
+		self.buf_out.clear();
+		self.pos = 0;
+		if !self.wrote_header {
+			self.buf_out.extend_from_slice(&MAGIC_CHUNK);
+			self.wrote_header = true;
+		}
+		let (chunk_header, chunk_body) = self.chunks.encode(&self.buf_src)?;
+		self.buf_out.extend_from_slice(chunk_header);
+		self.buf_out.extend_from_slice(chunk_body);
+		self.buf_src.clear();
+		Ok(())
+	}
+
+	// Writes out buf_out with write, which behaves like poll_write on the
+	// inner writer.
+	fn poll_drain<F>(&mut self, mut write: F) -> Poll<io::Result<()>>
+		where F: FnMut(Pin<&mut W>, &[u8]) -> Poll<io::Result<usize>>, W: Unpin {
+		while self.pos < self.buf_out.len() {
+			let n = ready!(write(Pin::new(&mut self.inner), &self.buf_out[self.pos..]))?;
+			if n == 0 {
+				return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+			}
+			self.pos += n;
+		}
+		Poll::Ready(Ok(()))
+	}
+
+	// Writes out all pending output and any staged input, with write.
+	fn poll_emit<F>(&mut self, mut write: F) -> Poll<io::Result<()>>
+		where F: FnMut(Pin<&mut W>, &[u8]) -> Poll<io::Result<usize>>, W: Unpin {
+		ready!(self.poll_drain(&mut write))?;
+		self.encode_staged()?;
+		self.poll_drain(write)
+	}
+}
+
+#[cfg(feature = "tokio")]
+impl <W: tokio::io::AsyncWrite + Unpin> tokio::io::AsyncWrite for AsyncCompressor<W> {
+	fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, src: &[u8]) -> Poll<io::Result<usize>> {
+		let this = self.get_mut();
+		// A full chunk may still be waiting to be written.
+		ready!(this.poll_drain(|w, buf| w.poll_write(cx, buf)))?;
+		Poll::Ready(this.stage(src))
+	}
+
+	fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		let this = self.get_mut();
+		ready!(this.poll_emit(|w, buf| w.poll_write(cx, buf)))?;
+		Pin::new(&mut this.inner).poll_flush(cx)
+	}
+
+	fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		let this = self.get_mut();
+		ready!(this.poll_emit(|w, buf| w.poll_write(cx, buf)))?;
+		Pin::new(&mut this.inner).poll_shutdown(cx)
+	}
+}
+
+#[cfg(feature = "futures")]
+impl <W: futures_io::AsyncWrite + Unpin> futures_io::AsyncWrite for AsyncCompressor<W> {
+	fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, src: &[u8]) -> Poll<io::Result<usize>> {
+		let this = self.get_mut();
+		// A full chunk may still be waiting to be written.
+		ready!(this.poll_drain(|w, buf| w.poll_write(cx, buf)))?;
+		Poll::Ready(this.stage(src))
+	}
+
+	fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		let this = self.get_mut();
+		ready!(this.poll_emit(|w, buf| w.poll_write(cx, buf)))?;
+		Pin::new(&mut this.inner).poll_flush(cx)
+	}
+
+	fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+		let this = self.get_mut();
+		ready!(this.poll_emit(|w, buf| w.poll_write(cx, buf)))?;
+		Pin::new(&mut this.inner).poll_close(cx)
+	}
+}
+
+// AsyncDecompressor reads the framing format from an async reader. It checks
+// the stream exactly as the Decompressor does, and reports errors the same way.
+// The inner reader is read a chunk header and a chunk body at a time, so wrap
+// it in a BufReader if small reads are costly.
+pub struct AsyncDecompressor<R> {
+	inner: R,
+	// The decoder holds a whole decoded chunk; boxing it keeps the futures
+	// that own an AsyncDecompressor small.
+	chunks: Box<ChunkDecoder>,
+}
+
+impl <R> AsyncDecompressor<R> {
+
+	pub fn new(inner: R) -> AsyncDecompressor<R> {
+		AsyncDecompressor::with_limit(inner, u64::MAX)
+	}
+
+	// Creates an AsyncDecompressor which decodes at most limit bytes in
+	// total, as Decompressor::with_limit does.
+	pub fn with_limit(inner: R, limit: u64) -> AsyncDecompressor<R> {
+		AsyncDecompressor {
+			inner,
+			chunks: Box::new(ChunkDecoder::new(limit)),
+		}
+	}
+
+	// Gets a reference to the inner reader.
+	pub fn get_ref(&self) -> &R {
+		&self.inner
+	}
+
+	// Gets a mutable reference to the inner reader. Reading from it directly
+	// will corrupt the framed stream.
+	pub fn get_mut(&mut self) -> &mut R {
+		&mut self.inner
+	}
+
+	// Returns the inner reader. Any decoded data that has not been read is
+	// lost.
+	pub fn into_inner(self) -> R {
+		self.inner
+	}
+
+	// Reads and decodes chunks with read, which behaves like poll_read on the
+	// inner reader, until decoded data is available. Returns false at a clean
+	// end of stream.
+	fn poll_fill<F>(&mut self, mut read: F) -> Poll<io::Result<bool>>
+		where F: FnMut(Pin<&mut R>, &mut [u8]) -> Poll<io::Result<usize>>, R: Unpin {
+		let inner = &mut self.inner;
+		let mut pending = false;
+		// The decoder keeps its progress when a read fails, so a pending read
+		// can be passed through as an error and retried on the next poll.
+		let res = self.chunks.fill(|buf| match read(Pin::new(inner), buf) {
+			Poll::Ready(res) => res,
+			Poll::Pending => {
+				pending = true;
+				Err(io::ErrorKind::WouldBlock.into())
+			},
+		});
+		if pending {
+			return Poll::Pending;
+		}
+		Poll::Ready(res)
+	}
+}
+
+#[cfg(feature = "tokio")]
+fn tokio_read<R>(r: Pin<&mut R>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>>
+	where R: tokio::io::AsyncRead {
+	let mut buf = tokio::io::ReadBuf::new(buf);
+	ready!(r.poll_read(cx, &mut buf))?;
+	Poll::Ready(Ok(buf.filled().len()))
+}
+
+#[cfg(feature = "tokio")]
+impl <R: tokio::io::AsyncRead + Unpin> tokio::io::AsyncRead for AsyncDecompressor<R> {
+	fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut tokio::io::ReadBuf<'_>) -> Poll<io::Result<()>> {
+		let this = self.get_mut();
+		if ready!(this.poll_fill(|r, dst| tokio_read(r, cx, dst)))? {
+			let decoded = this.chunks.decoded();
+			let n = ::std::cmp::min(buf.remaining(), decoded.len());
+			buf.put_slice(&decoded[..n]);
+			this.chunks.consume(n);
+		}
+		Poll::Ready(Ok(()))
+	}
+}
+
+// Hands out decoded bytes straight from the internal buffer, a chunk at a time.
+#[cfg(feature = "tokio")]
+impl <R: tokio::io::AsyncRead + Unpin> tokio::io::AsyncBufRead for AsyncDecompressor<R> {
+	fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+		let this = self.get_mut();
+		ready!(this.poll_fill(|r, dst| tokio_read(r, cx, dst)))?;
+		Poll::Ready(Ok(this.chunks.decoded()))
+	}
+
+	fn consume(self: Pin<&mut Self>, amt: usize) {
+		self.get_mut().chunks.consume(amt);
+	}
+}
+
+#[cfg(feature = "futures")]
+impl <R: futures_io::AsyncRead + Unpin> futures_io::AsyncRead for AsyncDecompressor<R> {
+	fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+		let this = self.get_mut();
+		if !ready!(this.poll_fill(|r, dst| r.poll_read(cx, dst)))? {
+			return Poll::Ready(Ok(0));
+		}
+		let decoded = this.chunks.decoded();
+		let n = ::std::cmp::min(buf.len(), decoded.len());
+		buf[..n].copy_from_slice(&decoded[..n]);
+		this.chunks.consume(n);
+		Poll::Ready(Ok(n))
+	}
+}
+
+// Hands out decoded bytes straight from the internal buffer, a chunk at a time.
+#[cfg(feature = "futures")]
+impl <R: futures_io::AsyncRead + Unpin> futures_io::AsyncBufRead for AsyncDecompressor<R> {
+	fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+		let this = self.get_mut();
+		ready!(this.poll_fill(|r, dst| r.poll_read(cx, dst)))?;
+		Poll::Ready(Ok(this.chunks.decoded()))
+	}
+
+	fn consume(self: Pin<&mut Self>, amt: usize) {
+		self.get_mut().chunks.consume(amt);
+	}
+}
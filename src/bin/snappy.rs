@@ -0,0 +1,324 @@
+// snappy compresses and decompresses files in the framing format, or as raw
+// blocks with --raw.
+
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use snappy_rust::{ChunkInfo, Compressor, Decompressor, Error, compress_to_vec, decompress_to_vec, inspect_stream};
+
+const USAGE: &str = "\
+usage: snappy [-c | -d] [--raw] [-k] [-f] [FILE...]
+       snappy inspect [FILE]
+
+Compresses each FILE to FILE.sz, or decompresses each FILE.sz to FILE, and
+removes FILE unless -k is given. With no FILE, or when FILE is -, reads
+standard input and writes standard output.
+
+  -c, --compress     compress (the default)
+  -d, --decompress   decompress
+      --raw          use a single raw block, suffix .rawsnappy, instead of the
+                     framing format
+  -k, --keep         keep the input files
+  -f, --force        overwrite existing output files
+  -h, --help         print this help
+
+snappy inspect lists the chunks of the framed stream in FILE, or standard
+input, with their offsets, types, lengths, stored and computed checksums and
+decoded lengths.
+
+Exit status is 0 on success, 1 if some input is corrupt, 2 on I/O errors and
+3 on usage errors.";
+
+// Exit codes.
+const EXIT_CORRUPT: i32 = 1;
+const EXIT_IO: i32 = 2;
+const EXIT_USAGE: i32 = 3;
+
+struct Options {
+	decompress: bool,
+	raw: bool,
+	keep: bool,
+	force: bool,
+	files: Vec<String>,
+}
+
+impl Options {
+
+	fn parse<I: Iterator<Item = String>>(args: I) -> Result<Options, String> {
+		let mut opts = Options { decompress: false, raw: false, keep: false, force: false, files: Vec::new() };
+		let mut only_files = false;
+
+		for arg in args {
+			if only_files || arg == "-" || !arg.starts_with('-') {
+				opts.files.push(arg);
+				continue;
+			}
+			match arg.as_str() {
+				"--" => only_files = true,
+				"--compress" => opts.decompress = false,
+				"--decompress" => opts.decompress = true,
+				"--raw" => opts.raw = true,
+				"--keep" => opts.keep = true,
+				"--force" => opts.force = true,
+				"--help" => return Err(String::new()),
+				_ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+				// Short options may be combined, as in -dk.
+				_ => for flag in arg[1..].chars() {
+					match flag {
+						'c' => opts.decompress = false,
+						'd' => opts.decompress = true,
+						'k' => opts.keep = true,
+						'f' => opts.force = true,
+						'h' => return Err(String::new()),
+						_ => return Err(format!("unknown option -{}", flag)),
+					}
+				},
+			}
+		}
+		Ok(opts)
+	}
+
+	fn suffix(&self) -> &'static str {
+		if self.raw { ".rawsnappy" } else { ".sz" }
+	}
+}
+
+// Failure is why processing an input failed, and decides the exit code.
+enum Failure {
+	Corrupt(String),
+	Io(String),
+	Usage(String),
+}
+
+impl Failure {
+
+	// Classifies err, which occurred while processing what, as corrupt input
+	// or as a plain I/O error.
+	fn from_io(what: &str, err: io::Error) -> Failure {
+		if err.kind() == io::ErrorKind::AlreadyExists {
+			return Failure::Io(format!("{}: already exists, use -f to overwrite", what));
+		}
+		// The library's errors already say where they come from.
+		let msg = format!("{}: {}", what, err.to_string().trim_start_matches("snappy: "));
+		match err.get_ref().and_then(|e| e.downcast_ref::<Error>()) {
+			Some(e) if is_corrupt(e) => Failure::Corrupt(msg),
+			_ => Failure::Io(msg),
+		}
+	}
+
+	fn exit_code(&self) -> i32 {
+		match *self {
+			Failure::Corrupt(_) => EXIT_CORRUPT,
+			Failure::Io(_) => EXIT_IO,
+			Failure::Usage(_) => EXIT_USAGE,
+		}
+	}
+
+	fn message(&self) -> &str {
+		match *self {
+			Failure::Corrupt(ref msg) | Failure::Io(ref msg) | Failure::Usage(ref msg) => msg,
+		}
+	}
+}
+
+// Whether err means the compressed input is bad, rather than that it could
+// not be read or written.
+fn is_corrupt(err: &Error) -> bool {
+	!matches!(*err, Error::BufferTooSmall { .. } | Error::TooLarge)
+}
+
+// Compresses or decompresses all of r into w.
+fn process<R: Read, W: Write>(opts: &Options, mut r: R, mut w: W) -> io::Result<()> {
+	if opts.raw {
+		let mut src = Vec::new();
+		r.read_to_end(&mut src)?;
+		let dst = if opts.decompress {
+			decompress_to_vec(&src)?
+		} else {
+			compress_to_vec_checked(&src)?
+		};
+		w.write_all(&dst)?;
+		return w.flush();
+	}
+
+	if opts.decompress {
+		io::copy(&mut Decompressor::new(r), &mut w)?;
+		w.flush()
+	} else {
+		let mut c = Compressor::new(w);
+		io::copy(&mut r, &mut c)?;
+		c.finish().map(|_| ())
+	}
+}
+
+// compress_to_vec, but reporting an input too long for a block as an error
+// instead of panicking.
+fn compress_to_vec_checked(src: &[u8]) -> Result<Vec<u8>, Error> {
+	if src.len() as u64 > 0xffff_ffff {
+		return Err(Error::TooLarge);
+	}
+	Ok(compress_to_vec(src))
+}
+
+// Pipes standard input to standard output.
+fn process_stdio(opts: &Options) -> Result<(), Failure> {
+	let stdin = io::stdin();
+	let stdout = io::stdout();
+	process(opts, stdin.lock(), BufWriter::new(stdout.lock()))
+		.map_err(|e| Failure::from_io("(stdin)", e))
+}
+
+// Processes the file at name into its compressed or decompressed sibling.
+fn process_file(opts: &Options, name: &str) -> Result<(), Failure> {
+	let src = Path::new(name);
+	let dst = output_path(opts, src)?;
+
+	let input = File::open(src).map_err(|e| Failure::from_io(name, e))?;
+
+	let mut open = OpenOptions::new();
+	open.write(true);
+	if opts.force {
+		open.create(true).truncate(true);
+	} else {
+		open.create_new(true);
+	}
+	let output = open.open(&dst).map_err(|e| Failure::from_io(&dst.to_string_lossy(), e))?;
+
+	if let Err(e) = process(opts, input, BufWriter::new(output)) {
+		// Don't leave a partial output behind.
+		let _ = fs::remove_file(&dst);
+		return Err(Failure::from_io(name, e));
+	}
+
+	if !opts.keep {
+		fs::remove_file(src).map_err(|e| Failure::from_io(name, e))?;
+	}
+	Ok(())
+}
+
+// Returns where the output for src goes: src with the suffix added when
+// compressing, or removed when decompressing.
+fn output_path(opts: &Options, src: &Path) -> Result<PathBuf, Failure> {
+	let name = src.to_string_lossy();
+	if !opts.decompress {
+		return Ok(PathBuf::from(format!("{}{}", name, opts.suffix())));
+	}
+	match name.strip_suffix(opts.suffix()) {
+		Some(stem) if !stem.is_empty() => Ok(PathBuf::from(stem)),
+		_ => Err(Failure::Usage(format!("{}: unknown suffix, expected {}", name, opts.suffix()))),
+	}
+}
+
+// Lists the chunks of the framed stream read from r, and fails if any chunk,
+// or the stream as a whole, is bad.
+fn inspect<R: Read>(what: &str, r: R) -> Result<(), Failure> {
+	let stdout = io::stdout();
+	let mut out = BufWriter::new(stdout.lock());
+	let write_err = |e: io::Error| Failure::from_io("(stdout)", e);
+
+	writeln!(out, "{:>12}  {:<20}  {:>8}  {:>10}  {:>10}  {:>8}  error",
+		"offset", "type", "length", "stored", "actual", "decoded").map_err(write_err)?;
+	let mut bad = None;
+	for res in inspect_stream(r) {
+		let info = match res {
+			Ok(info) => info,
+			Err(e) => {
+				out.flush().map_err(write_err)?;
+				return Err(Failure::from_io(what, e));
+			},
+		};
+		writeln!(out, "{}", format_chunk(&info)).map_err(write_err)?;
+		if bad.is_none() {
+			if let Some(ref e) = info.error {
+				bad = Some(Failure::Corrupt(format!("{}: chunk {} at offset {}: {}",
+					what, info.index, info.offset, e.to_string().trim_start_matches("snappy: "))));
+			}
+		}
+	}
+	out.flush().map_err(write_err)?;
+	bad.map_or(Ok(()), Err)
+}
+
+// Formats info as a row of the table printed by inspect.
+fn format_chunk(info: &ChunkInfo) -> String {
+	let hex = |v: Option<u32>| v.map_or(String::from("-"), |v| format!("{:#010x}", v));
+	format!("{:>12}  {:<20}  {:>8}  {:>10}  {:>10}  {:>8}  {}",
+		info.offset,
+		info.type_name(),
+		info.len,
+		hex(info.stored_checksum),
+		hex(info.actual_checksum),
+		info.uncompressed_len.map_or(String::from("-"), |n| n.to_string()),
+		info.error.as_ref().map_or(String::from("-"), |e| e.to_string().trim_start_matches("snappy: ").to_string()))
+}
+
+// Runs snappy inspect with the arguments that follow it.
+fn inspect_main(args: &[String]) -> Result<(), Failure> {
+	match args {
+		[] => {
+			let stdin = io::stdin();
+			inspect("(stdin)", stdin.lock())
+		},
+		[name] if name == "-" => {
+			let stdin = io::stdin();
+			inspect("(stdin)", stdin.lock())
+		},
+		[name] if !name.starts_with('-') => {
+			let file = File::open(name).map_err(|e| Failure::from_io(name, e))?;
+			inspect(name, file)
+		},
+		_ => Err(Failure::Usage(String::from("inspect takes at most one FILE"))),
+	}
+}
+
+fn main() {
+	let args: Vec<String> = env::args().skip(1).collect();
+	if args.first().map(String::as_str) == Some("inspect") {
+		let code = match inspect_main(&args[1..]) {
+			Ok(()) => 0,
+			Err(f) => {
+				eprintln!("snappy: {}", f.message());
+				f.exit_code()
+			},
+		};
+		process::exit(code);
+	}
+
+	let opts = match Options::parse(args.into_iter()) {
+		Ok(opts) => opts,
+		Err(msg) => {
+			if msg.is_empty() {
+				println!("{}", USAGE);
+				process::exit(0);
+			}
+			eprintln!("snappy: {}\n\n{}", msg, USAGE);
+			process::exit(EXIT_USAGE);
+		},
+	};
+
+	let mut code = 0;
+	let mut report = |res: Result<(), Failure>| {
+		if let Err(f) = res {
+			eprintln!("snappy: {}", f.message());
+			// Report the first failure, but carry on with the other files.
+			if code == 0 {
+				code = f.exit_code();
+			}
+		}
+	};
+
+	if opts.files.is_empty() {
+		report(process_stdio(&opts));
+	}
+	for name in &opts.files {
+		if name == "-" {
+			report(process_stdio(&opts));
+		} else {
+			report(process_file(&opts, name));
+		}
+	}
+	process::exit(code);
+}
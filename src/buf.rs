@@ -0,0 +1,142 @@
+// Block compression from and to the bytes crate's buffers.
+
+use alloc::vec::Vec;
+use core::{ptr, slice};
+#[cfg(feature = "std")]
+use std::io::IoSlice;
+
+use bytes::{Buf, BufMut, BytesMut};
+
+use crate::error::{Error, Result};
+use crate::compress::{Encoder, max_compressed_len};
+use crate::decompress::Decoder;
+
+// compress_buf compresses the remaining bytes of src as a single block, which
+// it appends to dst, and returns the length appended. src is consumed, even
+// if compressing fails.
+pub fn compress_buf<B: Buf, M: BufMut>(src: &mut B, dst: &mut M) -> Result<usize> {
+	Encoder::new().compress_buf(src, dst)
+}
+
+// decompress_buf decodes the remaining bytes of src as a single block, which
+// it appends to dst, and returns the length appended. src is consumed, even
+// if decoding fails.
+pub fn decompress_buf<B: Buf>(src: &mut B, dst: &mut BytesMut) -> Result<usize> {
+	Decoder::new().decompress_buf(src, dst)
+}
+
+impl Encoder {
+
+	// compress_buf compresses the remaining bytes of src as a single block,
+	// like the compress_buf function.
+	//
+	// Copies may reach back across the whole block, so the chunks of src are
+	// read as one input where they lie, and the block is the one compress
+	// writes for the same bytes. It is written straight into dst when dst
+	// offers max_compressed_len bytes of contiguous room, and is otherwise
+	// built aside and copied in.
+	pub fn compress_buf<B: Buf, M: BufMut>(&mut self, src: &mut B, dst: &mut M) -> Result<usize> {
+		let max = max_compressed_len(src.remaining());
+
+		let room = dst.chunk_mut();
+		if room.len() >= max {
+			// The room may be uninitialized, so it is zeroed before the
+			// encoder is given it as a slice.
+			let block = unsafe {
+				ptr::write_bytes(room.as_mut_ptr(), 0, max);
+				slice::from_raw_parts_mut(room.as_mut_ptr(), max)
+			};
+			let n = self.compress_remaining(block, src)?;
+			// The first n bytes of the room now hold the block.
+			unsafe { dst.advance_mut(n) };
+			return Ok(n);
+		}
+
+		let mut block = vec![0; max];
+		let n = self.compress_remaining(&mut block, src)?;
+		if dst.remaining_mut() < n {
+			return Err(Error::BufferTooSmall { needed: n });
+		}
+		dst.put_slice(&block[..n]);
+		Ok(n)
+	}
+
+	// Compresses the remaining bytes of src into dst, consuming them.
+	fn compress_remaining<B: Buf>(&mut self, dst: &mut [u8], src: &mut B) -> Result<usize> {
+		let len = src.remaining();
+		if src.chunk().len() == len {
+			let res = self.compress(dst, src.chunk());
+			src.advance(len);
+			return res;
+		}
+
+		#[cfg(feature = "std")]
+		{
+			if let Some(res) = self.compress_vectored(dst, src) {
+				src.advance(len);
+				return res;
+			}
+		}
+
+		// Otherwise take the chunks one at a time, which only copies those
+		// not already held in Bytes.
+		let mut chunks = Vec::new();
+		while src.has_remaining() {
+			let n = src.chunk().len();
+			chunks.push(src.copy_to_bytes(n));
+		}
+		self.compress_slices(dst, &chunks)
+	}
+
+	// Compresses the chunks of src where they lie, if src lists them all
+	// through chunks_vectored.
+	#[cfg(feature = "std")]
+	fn compress_vectored<B: Buf>(&mut self, dst: &mut [u8], src: &B) -> Option<Result<usize>> {
+		let len = src.remaining();
+		let mut slices = vec![IoSlice::new(&[]); 16];
+		loop {
+			let n = src.chunks_vectored(&mut slices);
+			if slices[..n].iter().map(|slice| slice.len()).sum::<usize>() == len {
+				return Some(self.compress_slices(dst, &slices[..n]));
+			}
+			// A Buf that leaves some of the slices unfilled can't list any
+			// more of its chunks.
+			if n < slices.len() {
+				return None;
+			}
+			let more = 2 * slices.len();
+			slices.resize(more, IoSlice::new(&[]));
+		}
+	}
+}
+
+impl Decoder {
+
+	// decompress_buf decodes the remaining bytes of src as a single block,
+	// like the decompress_buf function, but with this Decoder's options. The
+	// block is decoded straight into dst, which grows by exactly the decoded
+	// length; only a src held in several chunks is gathered into one first.
+	pub fn decompress_buf<B: Buf>(&self, src: &mut B, dst: &mut BytesMut) -> Result<usize> {
+		let len = src.remaining();
+		if src.chunk().len() == len {
+			let res = self.decompress_into_bytes(src.chunk(), dst);
+			src.advance(len);
+			res
+		} else {
+			self.decompress_into_bytes(&src.copy_to_bytes(len), dst)
+		}
+	}
+
+	// Appends the decoded form of the contiguous block src to dst.
+	fn decompress_into_bytes(&self, src: &[u8], dst: &mut BytesMut) -> Result<usize> {
+		let n = self.plausible_len(src)?;
+		let start = dst.len();
+		dst.reserve(n);
+		dst.resize(start + n, 0);
+		let res = self.decompress(&mut dst[start..], src);
+		if res.is_err() {
+			dst.truncate(start);
+		}
+		res
+	}
+}
@@ -0,0 +1,171 @@
+// SnappyCodec frames snappy-compressed messages for tokio_util's Framed. On
+// the wire, each message is a 4 byte big-endian length followed by that many
+// bytes of payload: the message compressed as a single block, or as a framing
+// format stream.
+
+use std::io;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use tokio_util::codec;
+
+use crate::definitions::*;
+use crate::error::Error;
+use crate::compress::{ChunkEncoder, CompressionPolicy, Encoder, max_compressed_len};
+use crate::decompress::{ChunkDecoder, Decoder};
+
+// The length of the big-endian length that precedes each payload.
+const LENGTH_FIELD_LEN: usize = 4;
+
+// Payload is how SnappyCodec compresses each message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Payload {
+	// A single block, as written by compress.
+	Raw,
+	// A framing format stream, as written by the Compressor, which carries
+	// checksums of the data.
+	Framed,
+}
+
+// SnappyCodec encodes messages as length-delimited snappy payloads and
+// decodes them back. A payload that fails to decode is consumed from the
+// stream along with its length, so later messages still decode; a length over
+// the limit is not, as the stream can't be trusted past it.
+pub struct SnappyCodec {
+	payload: Payload,
+	max_frame_len: usize,
+	encoder: Encoder,
+	chunks: ChunkEncoder,
+}
+
+impl SnappyCodec {
+
+	// Creates a codec for raw block payloads, with frames of at most 8 MiB.
+	pub fn new() -> SnappyCodec {
+		SnappyCodec {
+			payload: Payload::Raw,
+			max_frame_len: 8 * 1024 * 1024,
+			encoder: Encoder::new(),
+			chunks: ChunkEncoder::new(CompressionPolicy::default()),
+		}
+	}
+
+	// Sets how messages are compressed.
+	pub fn payload(mut self, payload: Payload) -> SnappyCodec {
+		self.payload = payload;
+		self
+	}
+
+	// Sets the largest message to accept, both compressed, as sent on the
+	// wire, and decoded. Encoding a message that exceeds it fails with
+	// Error::TooLarge, and decoding one with Error::LimitExceeded; a length
+	// over the limit is refused before the payload is buffered. The limit is
+	// capped to what the length field can hold.
+	pub fn max_frame_len(mut self, max_frame_len: usize) -> SnappyCodec {
+		self.max_frame_len = ::std::cmp::min(max_frame_len, u32::MAX as usize);
+		self
+	}
+
+	// Appends the compressed form of msg, preceded by its length, to dst.
+	fn encode_msg(&mut self, msg: &[u8], dst: &mut BytesMut) -> io::Result<()> {
+		if msg.len() > self.max_frame_len {
+			return Err(Error::TooLarge.into());
+		}
+
+		let start = dst.len();
+		dst.put_u32(0);
+		match self.payload {
+			Payload::Raw => {
+				let body = dst.len();
+				dst.resize(body + max_compressed_len(msg.len()), 0);
+				let n = self.encoder.compress(&mut dst[body..], msg)?;
+				dst.truncate(body + n);
+			},
+			Payload::Framed => {
+				dst.extend_from_slice(&MAGIC_CHUNK);
+				for chunk in msg.chunks(MAX_UNCOMPRESSED_CHUNK_LEN as usize) {
+					let (chunk_header, chunk_body) = self.chunks.encode(chunk)?;
+					dst.extend_from_slice(chunk_header);
+					dst.extend_from_slice(chunk_body);
+				}
+			},
+		}
+
+		let len = dst.len() - start - LENGTH_FIELD_LEN;
+		if len > self.max_frame_len {
+			dst.truncate(start);
+			return Err(Error::TooLarge.into());
+		}
+		dst[start..start + LENGTH_FIELD_LEN].copy_from_slice(&(len as u32).to_be_bytes());
+		Ok(())
+	}
+
+	// Decodes a single payload.
+	fn decode_payload(&self, payload: &[u8]) -> io::Result<BytesMut> {
+		match self.payload {
+			Payload::Raw => {
+				let decoder = Decoder::new().max_len(self.max_frame_len);
+				let len = decoder.plausible_len(payload)?;
+				let mut msg = BytesMut::zeroed(len);
+				decoder.decompress(&mut msg, payload)?;
+				Ok(msg)
+			},
+			Payload::Framed => {
+				let mut src = payload;
+				let mut chunks = ChunkDecoder::new(self.max_frame_len as u64);
+				let mut msg = BytesMut::new();
+				while chunks.fill(|buf| io::Read::read(&mut src, buf))? {
+					let decoded = chunks.decoded();
+					msg.extend_from_slice(decoded);
+					let n = decoded.len();
+					chunks.consume(n);
+				}
+				Ok(msg)
+			},
+		}
+	}
+}
+
+impl Default for SnappyCodec {
+	fn default() -> SnappyCodec {
+		SnappyCodec::new()
+	}
+}
+
+impl codec::Decoder for SnappyCodec {
+	type Item = BytesMut;
+	type Error = io::Error;
+
+	fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<BytesMut>> {
+		if src.len() < LENGTH_FIELD_LEN {
+			return Ok(None);
+		}
+		let len = u32::from_be_bytes([src[0], src[1], src[2], src[3]]) as usize;
+		if len > self.max_frame_len {
+			return Err(Error::LimitExceeded { limit: self.max_frame_len as u64 }.into());
+		}
+		if src.len() < LENGTH_FIELD_LEN + len {
+			src.reserve(LENGTH_FIELD_LEN + len - src.len());
+			return Ok(None);
+		}
+
+		src.advance(LENGTH_FIELD_LEN);
+		let payload = src.split_to(len);
+		self.decode_payload(&payload).map(Some)
+	}
+}
+
+impl <'a> codec::Encoder<&'a [u8]> for SnappyCodec {
+	type Error = io::Error;
+
+	fn encode(&mut self, msg: &'a [u8], dst: &mut BytesMut) -> io::Result<()> {
+		self.encode_msg(msg, dst)
+	}
+}
+
+impl codec::Encoder<Bytes> for SnappyCodec {
+	type Error = io::Error;
+
+	fn encode(&mut self, msg: Bytes, dst: &mut BytesMut) -> io::Result<()> {
+		self.encode_msg(&msg, dst)
+	}
+}
@@ -1,115 +1,468 @@
-extern crate byteorder;
-extern crate crc;
-
+use alloc::vec::Vec;
+#[cfg(any(feature = "std", feature = "bytes"))]
+use core::cell::Cell;
+#[cfg(any(feature = "std", feature = "bytes"))]
+use core::ops::Deref;
+#[cfg(feature = "std")]
+use std::error;
+#[cfg(feature = "std")]
+use std::fmt;
+#[cfg(feature = "std")]
 use std::io;
-use std::io::{BufWriter, ErrorKind, Write, Seek, SeekFrom, Result, Error};
-use self::byteorder::{ByteOrder, WriteBytesExt, LittleEndian};
-use self::crc::{crc32, Hasher32};
+#[cfg(feature = "std")]
+use std::io::{BufWriter, IoSlice, Write, Seek, SeekFrom};
 
-use definitions::*;
+use crate::definitions::*;
+#[cfg(feature = "std")]
+use crate::crc32c::masked_checksum;
+use crate::error::{Error, Result};
 
 
-// We limit how far copy back-references can go, the same as the C++ code.
-const MAX_OFFSET: usize = 1 << 15;
+// We limit how far copy back-references can go, so that offsets always fit in
+// the 32 bits of a COPY_4 tag, used for offsets of 1<<16 and beyond.
+const MAX_OFFSET: usize = 1 << 31;
 
 // The Max Encoded Length of the Max Chunk of 65536 bytes
+#[cfg(feature = "std")]
 const MAX_BUFFER_SIZE: usize = 76_490;
 
+// CompressionPolicy decides whether the Compressor stores each data chunk in
+// its compressed or its uncompressed form. Uncompressed chunks are cheaper to
+// decode, compressed ones are smaller.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CompressionPolicy {
+    // Always store the compressed form, even when it is larger.
+    Always,
+    // Store the compressed form only when it saves at least the given
+    // fraction of the chunk, e.g. 0.125 for 12.5%.
+    Threshold(f64),
+    // Never compress; every chunk is stored as is.
+    Never,
+}
+
+// The framing format's suggested policy, which keeps the compressed form only
+// if the improvement is at least 12.5%.
+impl Default for CompressionPolicy {
+    fn default() -> CompressionPolicy {
+        CompressionPolicy::Threshold(0.125)
+    }
+}
+
+// Compressor writes the framing format. Input is staged until a full chunk of
+// MAX_UNCOMPRESSED_CHUNK_LEN bytes is available, so many small writes still
+// produce full-size chunks. A partial chunk is only emitted on flush, finish,
+// or when the Compressor is dropped.
+#[cfg(feature = "std")]
 pub struct Compressor<W: Write> {
-    inner: BufWriter<W>,
+    // inner is only None once into_inner has taken it.
+    inner: Option<BufWriter<W>>,
     pos: u64,
-    buf_body: [u8; MAX_BUFFER_SIZE],
-    buf_header: [u8; (CHECK_SUM_SIZE + CHUNK_HEADER_SIZE) as usize],
-    wrote_header: bool,
+    // buf_src holds input that has not been emitted as a chunk yet.
+    buf_src: Vec<u8>,
+    chunks: ChunkEncoder,
+    chunk_writer: ChunkWriter,
+    // pending holds a chunk, header and body, which the writer failed to take
+    // all of. The rest of it is written before anything else.
+    pending: Vec<u8>,
 }
 
+#[cfg(feature = "std")]
 impl <W: Write> Compressor<W> {
 
     pub fn new(inner: W) -> Compressor<W> {
+        Compressor::with_policy(inner, CompressionPolicy::default())
+    }
+
+    // Creates a Compressor which applies policy to decide which chunks to
+    // store compressed.
+    pub fn with_policy(inner: W, policy: CompressionPolicy) -> Compressor<W> {
         Compressor {
-            inner: BufWriter::new(inner),
+            inner: Some(BufWriter::new(inner)),
             pos: 0,
-            buf_body: [0; MAX_BUFFER_SIZE],
-            buf_header: [0; (CHECK_SUM_SIZE + CHUNK_HEADER_SIZE) as usize],
-            wrote_header: false,
+            buf_src: Vec::with_capacity(MAX_UNCOMPRESSED_CHUNK_LEN as usize),
+            chunks: ChunkEncoder::new(policy),
+            chunk_writer: ChunkWriter::default(),
+            pending: Vec::new(),
+        }
+    }
+
+    // Gets a reference to the inner writer.
+    pub fn get_ref(&self) -> &W {
+        self.inner.as_ref().unwrap().get_ref()
+    }
+
+    // Gets a mutable reference to the inner writer. Writing to it directly
+    // will corrupt the framed stream.
+    pub fn get_mut(&mut self) -> &mut W {
+        self.inner.as_mut().unwrap().get_mut()
+    }
+
+    // Emits any staged input, flushes, and returns the inner writer. On error
+    // the Compressor is handed back, so that the caller may retry.
+    #[allow(clippy::result_large_err)]
+    pub fn into_inner(mut self) -> ::std::result::Result<W, IntoInnerError<Compressor<W>>> {
+        if let Err(err) = self.flush() {
+            return Err(IntoInnerError { inner: self, err });
+        }
+        let (inner, _) = self.inner.take().unwrap().into_parts();
+        Ok(inner)
+    }
+
+    // Finishes the stream: emits any staged input, flushes, and returns the
+    // inner writer.
+    pub fn finish(self) -> io::Result<W> {
+        self.into_inner().map_err(|e| e.err)
+    }
+
+    // Returns what the Compressor has done so far. Input still staged for the
+    // next chunk is not counted until that chunk is emitted.
+    pub fn stats(&self) -> &CompressStats {
+        &self.chunks.stats
+    }
+
+    fn writer(&mut self) -> &mut BufWriter<W> {
+        self.inner.as_mut().unwrap()
+    }
+
+    // Emits any staged input as a chunk.
+    fn write_staged(&mut self) -> io::Result<()> {
+        if self.buf_src.is_empty() {
+            return Ok(());
+        }
+        // Take the buffer out while writing, to borrow it alongside self.
+        let staged = ::core::mem::take(&mut self.buf_src);
+        let res = self.write_chunk(&staged);
+        self.buf_src = staged;
+        if res.is_ok() {
+            self.buf_src.clear();
+        }
+        res
+    }
+
+    // Takes all of src, emitting chunks as they fill up, and counts the bytes
+    // taken in written. On error, the bytes counted have still been taken.
+    fn stage_input(&mut self, mut src: &[u8], written: &mut usize) -> io::Result<()> {
+
+        let chunk_len = MAX_UNCOMPRESSED_CHUNK_LEN as usize;
+
+        while !src.is_empty() {
+
+            // With nothing staged, full chunks are compressed straight from src.
+            if self.buf_src.is_empty() && src.len() >= chunk_len {
+                let (chunk, rest) = src.split_at(chunk_len);
+                self.write_chunk(chunk)?;
+                *written += chunk_len;
+                src = rest;
+                continue;
+            }
+
+            // Otherwise, top up the staged chunk and emit it once it is full.
+            let n = ::core::cmp::min(chunk_len - self.buf_src.len(), src.len());
+            self.buf_src.extend_from_slice(&src[..n]);
+            *written += n;
+            src = &src[n..];
+
+            if self.buf_src.len() == chunk_len {
+                self.write_staged()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Emits src_chunk, of at most MAX_UNCOMPRESSED_CHUNK_LEN bytes, as a
+    // single chunk, preceded by the stream identifier if it is the first.
+    // Once it is encoded, src_chunk has been taken even if writing it fails:
+    // the rest of the chunk is kept in pending, and the error is reported by
+    // the next attempt to write it, if that fails too.
+    fn write_chunk(&mut self, src_chunk: &[u8]) -> io::Result<()> {
+        self.write_pending()?;
+
+        let wrote_header = self.chunk_writer.wrote_header();
+        let (chunk_header, chunk_body) = self.chunks.encode(src_chunk)?;
+        let inner = self.inner.as_mut().unwrap();
+        if self.chunk_writer.write(inner, &[chunk_header, chunk_body]).is_err() {
+            self.pending.extend_from_slice(chunk_header);
+            self.pending.extend_from_slice(chunk_body);
+        }
+        self.count_header(wrote_header);
+        Ok(())
+    }
+
+    // Writes the rest of the chunk in pending, if there is one.
+    fn write_pending(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let wrote_header = self.chunk_writer.wrote_header();
+        let inner = self.inner.as_mut().unwrap();
+        let res = self.chunk_writer.write(inner, &[&self.pending]);
+        self.count_header(wrote_header);
+        res?;
+        self.pending.clear();
+        Ok(())
+    }
+
+    // Counts the stream identifier once it has been written.
+    fn count_header(&mut self, wrote_header: bool) {
+        if !wrote_header && self.chunk_writer.wrote_header() {
+            self.chunks.stats.bytes_out += MAGIC_CHUNK.len() as u64;
         }
     }
 }
 
-impl <W: Write> Write for Compressor<W> {
-    // Implement Write
-    // Source Buffer -> Destination (Inner) Buffer
-    fn write(&mut self, src: &[u8]) -> Result<usize> {
+// ChunkWriter writes the stream identifier, and then chunks, to a writer,
+// keeping track of how much of each the writer has taken. When a write fails
+// part way through a chunk, the next one resumes where it stopped rather than
+// starting the chunk again, which would repeat the bytes already written.
+#[cfg(feature = "std")]
+#[derive(Default)]
+pub(crate) struct ChunkWriter {
+    header_pos: usize,
+    chunk_pos: usize,
+}
 
-        let mut written: usize = 0;
+#[cfg(feature = "std")]
+impl ChunkWriter {
+
+    // Returns whether all of the stream identifier has been written.
+    pub(crate) fn wrote_header(&self) -> bool {
+        self.header_pos == MAGIC_CHUNK.len()
+    }
+
+    // Writes the stream identifier, unless it has been written, and then the
+    // chunk made up of parts. After an error the same chunk must be passed
+    // again, and is written from where the failed write stopped.
+    pub(crate) fn write<W: Write>(&mut self, inner: &mut W, parts: &[&[u8]]) -> io::Result<()> {
+        write_from(inner, &MAGIC_CHUNK, &mut self.header_pos)?;
+
+        // chunk_pos counts through the parts as if they were one buffer.
+        let mut start = 0;
+        for part in parts {
+            if self.chunk_pos < start + part.len() {
+                let mut pos = self.chunk_pos - start;
+                let res = write_from(inner, part, &mut pos);
+                self.chunk_pos = start + pos;
+                res?;
+            }
+            start += part.len();
+        }
+        self.chunk_pos = 0;
+        Ok(())
+    }
+}
+
+// Writes buf from *pos on, moving *pos past each byte written, as
+// BufWriter::flush_buf does, so that a write which fails part way through can
+// be picked up where it stopped.
+#[cfg(feature = "std")]
+pub(crate) fn write_from<W: Write>(inner: &mut W, buf: &[u8], pos: &mut usize) -> io::Result<()> {
+    while *pos < buf.len() {
+        match inner.write(&buf[*pos..]) {
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::WriteZero, "snappy: failed to write the whole chunk")),
+            Ok(n) => *pos += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+// ChunkEncoder turns input into data chunks of the framing format. It does no
+// I/O itself, so that the blocking and async writers can share it.
+#[cfg(feature = "std")]
+pub(crate) struct ChunkEncoder {
+    encoder: Encoder,
+    policy: CompressionPolicy,
+    header: [u8; (CHECK_SUM_SIZE + CHUNK_HEADER_SIZE) as usize],
+    body: Vec<u8>,
+    // stats covers every chunk encoded, but not the stream identifier, which
+    // is written by the owner.
+    stats: CompressStats,
+}
+
+#[cfg(feature = "std")]
+impl ChunkEncoder {
 
-        if !self.wrote_header {
-            // Write Stream Literal
-            try!(self.inner.write(&MAGIC_CHUNK));
-            self.wrote_header = true;
+    pub(crate) fn new(policy: CompressionPolicy) -> ChunkEncoder {
+        ChunkEncoder {
+            encoder: Encoder::new(),
+            policy,
+            header: [0; (CHECK_SUM_SIZE + CHUNK_HEADER_SIZE) as usize],
+            body: vec![0; MAX_BUFFER_SIZE],
+            stats: CompressStats::default(),
         }
+    }
 
-        // Split source into chunks of 65536 bytes each.
-        for src_chunk in src.chunks(MAX_UNCOMPRESSED_CHUNK_LEN as usize) {
+    // Encodes src_chunk, of at most MAX_UNCOMPRESSED_CHUNK_LEN bytes, as a
+    // single chunk, and returns the chunk's header and body. The body is
+    // src_chunk itself when the chunk is stored uncompressed.
+    pub(crate) fn encode<'a>(&'a mut self, src_chunk: &'a [u8]) -> Result<(&'a [u8], &'a [u8])> {
 
-            // TODO 
-            // Handle Written Slice Length (Ignore Previous Garbage)
-            let chunk_body: &[u8];
-            let chunk_type: u8;
+        let chunk_body: &[u8];
+        let chunk_type: u8;
 
-            // Create Checksum
-            let checksum: u32 = crc32::checksum_ieee(src_chunk);
+        // Create Checksum
+        let checksum: u32 = masked_checksum(src_chunk);
 
-            // Compress the buffer, discarding the result if the improvement
-            // isn't at least 12.5%.
-            let n = try!(compress(&mut self.buf_body, src_chunk));
-            if n >= src_chunk.len() * (7 / 8) {
+        // Compress the buffer, discarding the result if the policy finds
+        // the improvement too small.
+        let mut block = CompressStats::default();
+        let compressed_len = match self.policy {
+            CompressionPolicy::Never => None,
+            CompressionPolicy::Always => Some(self.encoder.compress_with_stats(&mut self.body, src_chunk, &mut block)?),
+            CompressionPolicy::Threshold(saving) => {
+                let n = self.encoder.compress_with_stats(&mut self.body, src_chunk, &mut block)?;
+                if (n as f64) < src_chunk.len() as f64 * (1.0 - saving) {
+                    Some(n)
+                } else {
+                    None
+                }
+            },
+        };
+        match compressed_len {
+            Some(n) => {
+                chunk_type = CHUNK_TYPE_COMPRESSED_DATA;
+                chunk_body = &self.body[..n];
+            },
+            None => {
                 chunk_type = CHUNK_TYPE_UNCOMPRESSED_DATA;
                 chunk_body = src_chunk;
-            } else {
-                chunk_type = CHUNK_TYPE_COMPRESSED_DATA;
-                chunk_body = self.buf_body.split_at(n).0;
-            }
+            },
+        }
 
-            let chunk_len = chunk_body.len() + 4;
+        let chunk_len = chunk_body.len() + CHECK_SUM_SIZE as usize;
 
-            // Write Chunk Type
-            self.buf_header[0] = chunk_type;
-            // Write Chunk Length
-            self.buf_header[1] = (chunk_len >> 0) as u8;
-            self.buf_header[2] = (chunk_len >> 8) as u8;
-            self.buf_header[3] = (chunk_len >> 16) as u8;
-            // Write Chunk Checksum
-            self.buf_header[4] = (checksum >> 0) as u8;
-            self.buf_header[5] = (checksum >> 8) as u8;
-            self.buf_header[6] = (checksum >> 16) as u8;
-            self.buf_header[7] = (checksum >> 24) as u8;
+        // Count the chunk as written, rather than the block compressed for it.
+        block.bytes_in = src_chunk.len() as u64;
+        block.bytes_out = (CHUNK_HEADER_SIZE as usize + chunk_len) as u64;
+        block.chunks = 1;
+        block.uncompressed_chunks = (chunk_type == CHUNK_TYPE_UNCOMPRESSED_DATA) as u64;
+        self.stats.add(&block);
 
-            // Write Chunk Header and Handle Error
-            try!(self.inner.write_all(&self.buf_header));
-            // Write Chunk Body and Handle Error
-            try!(self.inner.write_all(chunk_body));
+        // Write Chunk Type
+        self.header[0] = chunk_type;
+        // Write Chunk Length
+        self.header[1] = chunk_len as u8;
+        self.header[2] = (chunk_len >> 8) as u8;
+        self.header[3] = (chunk_len >> 16) as u8;
+        // Write Chunk Checksum
+        self.header[4] = checksum as u8;
+        self.header[5] = (checksum >> 8) as u8;
+        self.header[6] = (checksum >> 16) as u8;
+        self.header[7] = (checksum >> 24) as u8;
+
+        Ok((&self.header, chunk_body))
+    }
+
+    // Returns the stats of the chunks encoded since the last call.
+    pub(crate) fn take_stats(&mut self) -> CompressStats {
+        ::core::mem::take(&mut self.stats)
+    }
+}
 
-            // If all goes well, count written length as uncompressed length
-            written += src_chunk.len();
+#[cfg(feature = "std")]
+impl <W: Write> Write for Compressor<W> {
+    // Implement Write
+    // Source Buffer -> Destination (Inner) Buffer
+    fn write(&mut self, src: &[u8]) -> io::Result<usize> {
+        let mut written: usize = 0;
+        match self.stage_input(src, &mut written) {
+            Err(e) if written == 0 => Err(e),
+            _ => Ok(written),
         }
+    }
 
+    // The slices are taken as one input, so they are cut into the same
+    // chunks as a single write of their concatenation would be.
+    fn write_vectored(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+        let mut written: usize = 0;
+        for buf in bufs {
+            if let Err(e) = self.stage_input(buf, &mut written) {
+                if written == 0 {
+                    return Err(e);
+                }
+                break;
+            }
+        }
         Ok(written)
     }
 
-    // Flushes Inner buffer and resets Compressor
-    fn flush(&mut self) -> Result<()> {
-        self.inner.flush()
+    // Emits any staged input as a chunk and flushes the Inner buffer
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_staged()?;
+        self.write_pending()?;
+        self.writer().flush()
+    }
+}
+
+// Emit any staged input. Errors can't be reported from here; call finish
+// to learn of them.
+#[cfg(feature = "std")]
+impl <W: Write> Drop for Compressor<W> {
+    fn drop(&mut self) {
+        if self.inner.is_some() {
+            let _ = self.write_staged().and_then(|()| self.write_pending());
+        }
+    }
+}
+
+// IntoInnerError is returned by Compressor::into_inner, and
+// ParallelCompressor::into_inner, when the stream could not be finished. It
+// holds the compressor along with the error.
+#[cfg(feature = "std")]
+pub struct IntoInnerError<C> {
+    pub(crate) inner: C,
+    pub(crate) err: io::Error,
+}
+
+#[cfg(feature = "std")]
+impl <C> IntoInnerError<C> {
+
+    // Returns the error which caused into_inner to fail.
+    pub fn error(&self) -> &io::Error {
+        &self.err
+    }
+
+    // Returns the Compressor, which still holds any input it could not emit.
+    pub fn into_inner(self) -> C {
+        self.inner
     }
 }
 
+#[cfg(feature = "std")]
+impl <C> From<IntoInnerError<C>> for io::Error {
+    fn from(e: IntoInnerError<C>) -> io::Error {
+        e.err
+    }
+}
+
+#[cfg(feature = "std")]
+impl <C> fmt::Debug for IntoInnerError<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.err.fmt(f)
+    }
+}
+
+#[cfg(feature = "std")]
+impl <C> fmt::Display for IntoInnerError<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.err.fmt(f)
+    }
+}
+
+#[cfg(feature = "std")]
+impl <C> error::Error for IntoInnerError<C> {}
+
 // If Compressor is Given a Cursor or Seekable Writer
 // This Gives the BufWriter the seek method
+#[cfg(feature = "std")]
 impl <W: Write + Seek> Seek for Compressor<W> {
-    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
-        self.inner.seek(pos).and_then(|res: u64| {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        // Staged input belongs at the current position.
+        self.write_staged()?;
+        self.writer().seek(pos).inspect(|&res: &u64| {
             self.pos = res;
-            Ok(res)
         })
     }
 }
@@ -120,122 +473,370 @@ impl <W: Write + Seek> Seek for Compressor<W> {
 // block.
 
 // (Future) Include a Legacy Compress??
-pub fn compress(dst: &mut [u8], src: &[u8]) -> io::Result<usize> {
+pub fn compress(dst: &mut [u8], src: &[u8]) -> Result<usize> {
+    Encoder::new().compress(dst, src)
+}
 
-    if dst.len() < max_compressed_len(src.len()) {
-        return Err(Error::new(ErrorKind::InvalidInput, "snappy: destination buffer is too short"));
-    }
+// compress_with_stats compresses src into dst, as compress does, and adds what
+// it did to stats. Counting costs a little time, which compress doesn't spend.
+pub fn compress_with_stats(dst: &mut [u8], src: &[u8], stats: &mut CompressStats) -> Result<usize> {
+    Encoder::new().compress_with_stats(dst, src, stats)
+}
 
-    // Start Block with varint-encoded length of decompressed bytes
-    LittleEndian::write_u64(dst, src.len() as u64);
+// compress_iovec writes the encoded form of the slices of src, read as one
+// input, into dst and returns the length written. The block is the same as
+// compress writes for the concatenated slices, but they are read where they
+// are instead of being concatenated first.
+#[cfg(feature = "std")]
+pub fn compress_iovec(dst: &mut [u8], src: &[IoSlice]) -> Result<usize> {
+    Encoder::new().compress_iovec(dst, src)
+}
 
-    let mut d: usize = 4;
+// The hash table's size ranges from 1<<8 to 1<<14 inclusive.
+const MAX_TABLE_SIZE: usize = 1 << 14;
 
-    // Return early if src is short
-    if src.len() <= 4 {
-        if src.len() != 0 {
-            // TODO Handle Error
-            d += emit_literal(dst.split_at_mut(d).1, src).unwrap();
+// Encoder compresses blocks, the same as `compress`, but owns its hash table
+// so that it can be reused across calls without reallocating it.
+//
+// Rather than zeroing the table for every block, table entries are stored
+// relative to a generation base that moves past every position used by the
+// previous block: any entry not greater than the base is stale and ignored.
+pub struct Encoder {
+    table: Vec<u32>,
+    base: u32,
+}
+
+impl Encoder {
+
+    pub fn new() -> Encoder {
+        Encoder {
+            table: vec![0; MAX_TABLE_SIZE],
+            base: 0,
         }
-        return Ok(d);
     }
 
-    // Initialize the hash table. Its size ranges from 1<<8 to 1<<14 inclusive.
-    const MAX_TABLE_SIZE: usize = 1 << 14;
-    let mut shift: u32 = 24;
-    let mut table_size: usize = 1 << 8;
+    // Compress writes the encoded form of src into dst and returns the length
+    // written.
+    // Returns an error if dst was not large enough to hold the entire encoded
+    // block.
+    pub fn compress(&mut self, dst: &mut [u8], src: &[u8]) -> Result<usize> {
+        self.compress_input(dst, src, &mut ())
+    }
 
-    while table_size < MAX_TABLE_SIZE && table_size < src.len() {
-        shift -= 1;
-        table_size *= 2;
+    // compress_with_stats compresses src into dst, as compress does, and adds
+    // what it did to stats. stats is left as it was if compressing fails.
+    pub fn compress_with_stats(&mut self, dst: &mut [u8], src: &[u8], stats: &mut CompressStats) -> Result<usize> {
+        let mut block = CompressStats::default();
+        let n = self.compress_input(dst, src, &mut block)?;
+        block.bytes_in = src.len() as u64;
+        block.bytes_out = n as u64;
+        stats.add(&block);
+        Ok(n)
     }
 
-    // Create table
-    let mut table: Vec<i32> = vec![0; MAX_TABLE_SIZE];
+    // compress_iovec writes the encoded form of the slices of src, read as
+    // one input, into dst and returns the length written, as the
+    // compress_iovec function does.
+    #[cfg(feature = "std")]
+    pub fn compress_iovec(&mut self, dst: &mut [u8], src: &[IoSlice]) -> Result<usize> {
+        self.compress_slices(dst, src)
+    }
+
+    // compress_slices writes the encoded form of the slices of src, read as
+    // one input, into dst and returns the length written.
+    #[cfg(any(feature = "std", feature = "bytes"))]
+    pub(crate) fn compress_slices<S: Deref<Target = [u8]>>(&mut self, dst: &mut [u8], src: &[S]) -> Result<usize> {
+        self.compress_input(dst, &Slices::new(src), &mut ())
+    }
 
-    // Iterate over the source bytes
-    let mut s: usize = 0;
-    let mut t: i32;
-    let mut lit: usize = 0;
+    fn compress_input<I: Input + ?Sized, T: Tally>(&mut self, dst: &mut [u8], src: &I, tally: &mut T) -> Result<usize> {
 
-    // (Future) Iterate in chunks of 4?
-    while s + 3 < src.len() {
+        if dst.len() < max_compressed_len(src.len()) {
+            return Err(Error::BufferTooSmall { needed: max_compressed_len(src.len()) });
+        }
 
-        // Grab 4 bytes
-        let b: (u8, u8, u8, u8) = (src[s], src[s + 1], src[s + 2], src[s + 3]);
-        
-        // Create u32 for Hashing
-        let h: u32 = (b.0 as u32) | ((b.1 as u32) << 8) | ((b.2 as u32) << 16) | ((b.3 as u32) << 24);
+        // The decoded length must fit in the 32 bits allowed by the format.
+        if src.len() as u64 > 0xffff_ffff {
+            return Err(Error::TooLarge);
+        }
 
-        // Update the hash table
-        let ref mut p: i32 = table[(h.wrapping_mul(0x1e35a7bd) >> shift) as usize];
+        // Start Block with varint-encoded length of decompressed bytes
+        let mut d: usize = emit_uvarint(dst, src.len() as u64);
 
-        // We need to to store values in [-1, inf) in table. To save
-        // some initialization time, (re)use the table's zero value
-        // and shift the values against this zero: add 1 on writes,
-        // subtract 1 on reads.
+        // Return early if src is short
+        if src.len() <= 4 {
+            if src.len() != 0 {
+                d += emit_literal(dst.split_at_mut(d).1, src, 0, src.len())?;
+                tally.literal(src.len());
+            }
+            return Ok(d);
+        }
 
-        // If Hash Exists t = 0; if not t = -1;
-        t = *p - 1;
+        // Size the hash table for src.
+        let mut shift: u32 = 24;
+        let mut table_size: usize = 1 << 8;
 
-        // Set Position of new Hash -> i32
-        *p = (s + 1) as i32;
+        while table_size < MAX_TABLE_SIZE && table_size < src.len() {
+            shift -= 1;
+            table_size *= 2;
+        }
 
-        // If t is invalid or src[s:s+4] differs from src[t:t+4], accumulate a literal
-        // byte.
-        if t < 0 || 
-            s.wrapping_sub(t as usize) >= MAX_OFFSET || 
-            b.0 != src[t as usize] || 
-            b.1 != src[(t + 1) as usize] || 
-            b.2 != src[(t + 2) as usize] || 
-            b.3 != src[(t + 3) as usize] {
-            s += 1;
-            continue;
+        // Start a new generation, clearing the table only once the base
+        // can no longer move past the positions of src.
+        if (u32::MAX - self.base) as usize <= src.len() {
+            for p in self.table.iter_mut() {
+                *p = 0;
+            }
+            self.base = 0;
         }
+        let base = self.base;
+        self.base += src.len() as u32;
+        let table = &mut self.table[..table_size];
 
-        // Otherwise, we have a match. First, emit any pending literal bytes.
-        // Panics on d > dst.len();
+        // Iterate over the source bytes
+        let mut s: usize = 0;
+        let mut lit: usize = 0;
 
-        if lit != s {
-            d += try!(emit_literal(dst.split_at_mut(d).1,
+        // (Future) Iterate in chunks of 4?
+        while s + 3 < src.len() {
 
-                // Handle Split_at mid < src check 
-                {
-                    if src.len() > s {
-                        src.split_at(s).0.split_at(lit).1
-                    } else {
-                        src.split_at(lit).1
-                    }
-                }));
+            // Grab 4 bytes
+            let b: (u8, u8, u8, u8) = (src.at(s), src.at(s + 1), src.at(s + 2), src.at(s + 3));
+
+            // Create u32 for Hashing
+            let h: u32 = (b.0 as u32) | ((b.1 as u32) << 8) | ((b.2 as u32) << 16) | ((b.3 as u32) << 24);
+
+            // Update the hash table
+            let p: &mut u32 = &mut table[(h.wrapping_mul(0x1e35a7bd) >> shift) as usize];
+
+            // Entries hold base + position + 1, so that the zero value and
+            // entries from previous generations are never greater than base.
+            let candidate = *p;
+            *p = base + s as u32 + 1;
+
+            // If there is no candidate, or src[s:s+4] differs from src[t:t+4],
+            // accumulate a literal byte.
+            if candidate <= base {
+                tally.lookup(false);
+                s += 1;
+                continue;
+            }
+            let t = (candidate - base - 1) as usize;
+            if s - t >= MAX_OFFSET ||
+                b.0 != src.at(t) ||
+                b.1 != src.at(t + 1) ||
+                b.2 != src.at(t + 2) ||
+                b.3 != src.at(t + 3) {
+                tally.lookup(false);
+                s += 1;
+                continue;
+            }
+            tally.lookup(true);
+
+            // Otherwise, we have a match. Extend it to be as long as possible.
+            let s0 = s;
+            let offset = s - t;
+            s = src.extend_match(s + 4, t + 4);
+
+            // A COPY_4 tag costs 5 bytes, so a far match only pays off if it is
+            // longer than that. Otherwise, accumulate a literal byte.
+            if offset >= 1 << 16 && s - s0 < 5 {
+                s = s0 + 1;
+                continue;
+            }
+
+            // Emit any pending literal bytes.
+            if lit != s0 {
+                d += emit_literal(dst.split_at_mut(d).1, src, lit, s0)?;
+                tally.literal(s0 - lit);
+            }
+
+            // Emit the copied bytes.
+            let n = emit_copy(dst.split_at_mut(d).1, offset, s - s0);
+            tally.copy(&dst[d..d + n], s - s0);
+            d += n;
+            lit = s;
         }
 
-        // Extend the match to be as long as possible
-        let s0 = s;
-        s += 4;
-        t += 4;
-        while s < src.len() && src[s] == src[t as usize] {
-            s += 1;
-            t += 1;
+        // Emit any final pending literal bytes and return.
+        if lit != src.len() {
+            d += emit_literal(dst.split_at_mut(d).1, src, lit, src.len())?;
+            tally.literal(src.len() - lit);
         }
 
-        // Emit the copied bytes.
-        d += emit_copy(dst.split_at_mut(d).1, s.wrapping_sub(t as usize), s - s0);
-        lit = s;
+        Ok(d)
     }
+}
+
+impl Default for Encoder {
+    fn default() -> Encoder {
+        Encoder::new()
+    }
+}
+
+// CompressStats counts what compressing did, to help judge how well some data
+// compresses. compress_with_stats adds each block to it, and a Compressor
+// accumulates it over every chunk it has emitted.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CompressStats {
+    // The length of the input, and of the output: the blocks, or for a
+    // Compressor, the whole framed stream written so far.
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    // The number of literal tags, and the bytes they hold.
+    pub literal_tags: u64,
+    pub literal_bytes: u64,
+    // The number of copy tags of each kind. A long match takes more than one
+    // tag, so matches counts the matches themselves, and match_bytes the
+    // bytes they copy.
+    pub copy1_tags: u64,
+    pub copy2_tags: u64,
+    pub copy4_tags: u64,
+    pub matches: u64,
+    pub match_bytes: u64,
+    // The number of hash table lookups, and how many of them found an
+    // earlier occurrence of the same 4 bytes.
+    pub table_lookups: u64,
+    pub table_hits: u64,
+    // The number of data chunks a Compressor emitted, and how many of them
+    // its policy stored uncompressed. The counts above include the blocks
+    // compressed for those chunks, though they were then discarded.
+    pub chunks: u64,
+    pub uncompressed_chunks: u64,
+}
+
+impl CompressStats {
 
-    // Emit any final pending literal bytes and return.
-    if lit != src.len() {
-        d += emit_literal(dst.split_at_mut(d).1, src.split_at(lit).1).unwrap();
+    // Returns the output length as a fraction of the input length, or 0 if
+    // there was no input.
+    pub fn ratio(&self) -> f64 {
+        fraction(self.bytes_out, self.bytes_in)
     }
 
-    Ok(d)
+    // Returns the mean length of a match, or 0 if there were none.
+    pub fn average_match_len(&self) -> f64 {
+        fraction(self.match_bytes, self.matches)
+    }
+
+    // Returns the fraction of hash table lookups that found a match, or 0 if
+    // there were none.
+    pub fn hit_rate(&self) -> f64 {
+        fraction(self.table_hits, self.table_lookups)
+    }
+
+    // Adds the counts of other to these, e.g. to total the stats of several
+    // Compressors.
+    pub fn add(&mut self, other: &CompressStats) {
+        self.bytes_in += other.bytes_in;
+        self.bytes_out += other.bytes_out;
+        self.literal_tags += other.literal_tags;
+        self.literal_bytes += other.literal_bytes;
+        self.copy1_tags += other.copy1_tags;
+        self.copy2_tags += other.copy2_tags;
+        self.copy4_tags += other.copy4_tags;
+        self.matches += other.matches;
+        self.match_bytes += other.match_bytes;
+        self.table_lookups += other.table_lookups;
+        self.table_hits += other.table_hits;
+        self.chunks += other.chunks;
+        self.uncompressed_chunks += other.uncompressed_chunks;
+    }
 }
 
-// emitLiteral writes a literal chunk and returns the number of bytes written.
-fn emit_literal(dst: &mut [u8], lit: &[u8]) -> io::Result<usize> {
+fn fraction(n: u64, d: u64) -> f64 {
+    if d == 0 { 0.0 } else { n as f64 / d as f64 }
+}
+
+// Tally is told what the encoder does as it goes. It is implemented by
+// CompressStats, and by () for when nothing is counted, which compiles away.
+trait Tally {
+    fn lookup(&mut self, hit: bool);
+    fn literal(&mut self, len: usize);
+    // tags are the copy tags emitted for a single match of len bytes.
+    fn copy(&mut self, tags: &[u8], len: usize);
+}
+
+impl Tally for () {
+    #[inline(always)]
+    fn lookup(&mut self, _: bool) {}
+    #[inline(always)]
+    fn literal(&mut self, _: usize) {}
+    #[inline(always)]
+    fn copy(&mut self, _: &[u8], _: usize) {}
+}
+
+impl Tally for CompressStats {
+    fn lookup(&mut self, hit: bool) {
+        self.table_lookups += 1;
+        self.table_hits += hit as u64;
+    }
+
+    fn literal(&mut self, len: usize) {
+        self.literal_tags += 1;
+        self.literal_bytes += len as u64;
+    }
+
+    fn copy(&mut self, tags: &[u8], len: usize) {
+        self.matches += 1;
+        self.match_bytes += len as u64;
+        let mut i = 0;
+        while i < tags.len() {
+            match tags[i] & 0x03 {
+                TAG_COPY_1 => { self.copy1_tags += 1; i += 2; },
+                TAG_COPY_2 => { self.copy2_tags += 1; i += 3; },
+                _ => { self.copy4_tags += 1; i += 5; },
+            }
+        }
+    }
+}
+
+// compress_to_vec returns the encoded form of src as a new Vec.
+// Panics if src is too long to be encoded as a single block.
+pub fn compress_to_vec(src: &[u8]) -> Vec<u8> {
+    let mut dst = Vec::new();
+    compress_into(src, &mut dst).expect("snappy: source buffer is too long");
+    dst
+}
+
+// compress_into appends the encoded form of src to dst and returns the length
+// appended. Room for max_compressed_len(src.len()) bytes is reserved up front,
+// so dst is grown at most once.
+pub fn compress_into(src: &[u8], dst: &mut Vec<u8>) -> Result<usize> {
+    let start = dst.len();
+    dst.resize(start + max_compressed_len(src.len()), 0);
+    match compress(&mut dst[start..], src) {
+        Ok(n) => {
+            dst.truncate(start + n);
+            Ok(n)
+        },
+        Err(e) => {
+            dst.truncate(start);
+            Err(e)
+        },
+    }
+}
+
+// emit_uvarint writes x as an unsigned varint and returns the number of bytes
+// written: 7 bits per byte, least significant group first, with the high bit
+// set on every byte except the last.
+fn emit_uvarint(dst: &mut [u8], mut x: u64) -> usize {
+    let mut i: usize = 0;
+    while x >= 0x80 {
+        dst[i] = (x as u8) | 0x80;
+        x >>= 7;
+        i += 1;
+    }
+    dst[i] = x as u8;
+    i + 1
+}
+
+// emitLiteral writes a literal chunk holding src[start:end] and returns the
+// number of bytes written.
+fn emit_literal<I: Input + ?Sized>(dst: &mut [u8], src: &I, start: usize, end: usize) -> Result<usize> {
 
     let i: usize;
-    let n: u64 = (lit.len() - 1) as u64;
+    let len = end - start;
+    let n: u64 = (len - 1) as u64;
 
     if n < 60 {
         dst[0] = (n as u8) << 2 | TAG_LITERAL;
@@ -263,20 +864,14 @@ fn emit_literal(dst: &mut [u8], lit: &[u8]) -> io::Result<usize> {
         dst[4] = (n >> 24) as u8;
         i = 5;
     } else {
-        return Err(Error::new(ErrorKind::InvalidInput, "snappy: source buffer is too long"));
+        return Err(Error::TooLarge);
     }
 
-    let mut s = 0;
-    for (d, l) in dst.split_at_mut(i).1.iter_mut().zip(lit.iter()) {
-        *d = *l;
-        s += 1;
-    }
-
-    if s == lit.len() {
-        Ok(i + lit.len())
-    } else {
-        return Err(Error::new(ErrorKind::InvalidInput, "snappy: destination buffer is too short"));
+    if dst.len() < i + len {
+        return Err(Error::BufferTooSmall { needed: i + len });
     }
+    src.copy_to(&mut dst[i..i + len], start);
+    Ok(i + len)
 }
 
 // emitCopy writes a copy chunk and returns the number of bytes written.
@@ -285,22 +880,29 @@ fn emit_copy(dst: &mut [u8], offset: usize, mut length: usize) -> usize {
     let mut i: usize = 0;
 
     while length > 0 {
-        // TODO: Handle Overflow
-        let mut x = length - 4;
-        if 0 <= x && x < 1 << 3 && offset < 1 << 11 {
-            dst[i] = ((offset >> 8) as u8) & 0x07 << 5 | (x as u8) << 2 | TAG_COPY_1;
+        if length >= 4 && length - 4 < 1 << 3 && offset < 1 << 11 {
+            dst[i] = ((offset >> 8) as u8 & 0x07) << 5 | ((length - 4) as u8) << 2 | TAG_COPY_1;
             dst[i + 1] = offset as u8;
             i += 2;
             break;
         }
-        x = length;
+        let mut x = length;
         if x > 1 << 6 {
             x = 1 << 6;
         }
-        dst[i] = ((x as u8) - 1) << 2 | TAG_COPY_2;
-        dst[i + 1] = offset as u8;
-        dst[i + 2] = (offset >> 8) as u8;
-        i += 3;
+        if offset < 1 << 16 {
+            dst[i] = ((x as u8) - 1) << 2 | TAG_COPY_2;
+            dst[i + 1] = offset as u8;
+            dst[i + 2] = (offset >> 8) as u8;
+            i += 3;
+        } else {
+            dst[i] = ((x as u8) - 1) << 2 | TAG_COPY_4;
+            dst[i + 1] = offset as u8;
+            dst[i + 2] = (offset >> 8) as u8;
+            dst[i + 3] = (offset >> 16) as u8;
+            dst[i + 4] = (offset >> 24) as u8;
+            i += 5;
+        }
         length -= x;
     }
     // (Future) Return a `Result<usize>` Instead??
@@ -312,3 +914,122 @@ fn emit_copy(dst: &mut [u8], offset: usize, mut length: usize) -> usize {
 pub fn max_compressed_len(src_len: usize) -> usize {
     32 + src_len + src_len / 6
 }
+
+// Input is what the encoder reads: a slice, or several slices read as one.
+// Positions are offsets into the whole input.
+trait Input {
+    fn len(&self) -> usize;
+
+    // Returns the byte at position i.
+    fn at(&self, i: usize) -> u8;
+
+    // Extends a match of the bytes at s with those at t, where t < s, and
+    // returns the position where the match ends.
+    fn extend_match(&self, s: usize, t: usize) -> usize;
+
+    // Copies the dst.len() bytes at position start into dst.
+    fn copy_to(&self, dst: &mut [u8], start: usize);
+}
+
+impl Input for [u8] {
+    fn len(&self) -> usize {
+        <[u8]>::len(self)
+    }
+
+    fn at(&self, i: usize) -> u8 {
+        self[i]
+    }
+
+    fn extend_match(&self, mut s: usize, mut t: usize) -> usize {
+        while s < self.len() && self[s] == self[t] {
+            s += 1;
+            t += 1;
+        }
+        s
+    }
+
+    fn copy_to(&self, dst: &mut [u8], start: usize) {
+        dst.copy_from_slice(&self[start..start + dst.len()]);
+    }
+}
+
+// Slices reads an array of slices, such as IoSlices, as one input.
+#[cfg(any(feature = "std", feature = "bytes"))]
+struct Slices<'a, S> {
+    slices: &'a [S],
+    // starts[k] is the position of slices[k] within the input, and the last
+    // entry is the length of the input.
+    starts: Vec<usize>,
+    // The slice that the last byte was read from, and most likely the next.
+    last: Cell<usize>,
+}
+
+#[cfg(any(feature = "std", feature = "bytes"))]
+impl <'a, S: Deref<Target = [u8]>> Slices<'a, S> {
+
+    fn new(slices: &'a [S]) -> Slices<'a, S> {
+        let mut starts = Vec::with_capacity(slices.len() + 1);
+        let mut pos = 0;
+        starts.push(pos);
+        for slice in slices {
+            pos += slice.len();
+            starts.push(pos);
+        }
+        Slices { slices, starts, last: Cell::new(0) }
+    }
+
+    // Returns the index of the non-empty slice which holds position i, which
+    // must be within the input.
+    fn find(&self, i: usize) -> usize {
+        let k = self.last.get();
+        if self.starts[k] <= i && i < self.starts[k + 1] {
+            return k;
+        }
+        // Empty slices share their start with the next slice, so the last
+        // slice that starts at or before i is the one which holds it.
+        let k = self.starts.partition_point(|&start| start <= i) - 1;
+        self.last.set(k);
+        k
+    }
+}
+
+#[cfg(any(feature = "std", feature = "bytes"))]
+impl <'a, S: Deref<Target = [u8]>> Input for Slices<'a, S> {
+    fn len(&self) -> usize {
+        self.starts[self.slices.len()]
+    }
+
+    fn at(&self, i: usize) -> u8 {
+        let k = self.find(i);
+        self.slices[k][i - self.starts[k]]
+    }
+
+    fn extend_match(&self, mut s: usize, mut t: usize) -> usize {
+        // Compare the runs of s and t that lie within a single slice each,
+        // moving on to the next slice as either run ends.
+        while s < self.len() {
+            let (ks, kt) = (self.find(s), self.find(t));
+            let a = &self.slices[ks][s - self.starts[ks]..];
+            let b = &self.slices[kt][t - self.starts[kt]..];
+            let n = a.iter().zip(b.iter()).take_while(|&(x, y)| x == y).count();
+            s += n;
+            t += n;
+            if n < a.len() && n < b.len() {
+                break;
+            }
+        }
+        s
+    }
+
+    fn copy_to(&self, dst: &mut [u8], start: usize) {
+        let mut d = 0;
+        let mut k = if dst.is_empty() { 0 } else { self.find(start) };
+        while d < dst.len() {
+            let slice = &self.slices[k][start + d - self.starts[k]..];
+            let n = ::core::cmp::min(slice.len(), dst.len() - d);
+            dst[d..d + n].copy_from_slice(&slice[..n]);
+            d += n;
+            k += 1;
+        }
+    }
+}
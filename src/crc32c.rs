@@ -0,0 +1,137 @@
+// CRC-32C (Castagnoli) checksums, as required by the framing format.
+//
+// https://github.com/google/snappy/blob/master/framing_format.txt says that
+// each data chunk carries "a CRC-32C checksum of the uncompressed data", masked
+// so that checksumming data which itself contains checksums stays robust.
+
+// The Castagnoli polynomial, in reversed bit order.
+const POLY: u32 = 0x82f6_3b78;
+
+// TABLE[0] is the classic byte-at-a-time table. TABLE[k][b] is the CRC of the
+// byte b followed by k zero bytes, which lets the portable path fold in 8 bytes
+// per step (slicing-by-8).
+static TABLE: [[u32; 256]; 8] = make_table();
+
+const fn make_table() -> [[u32; 256]; 8] {
+    let mut table = [[0u32; 256]; 8];
+
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ POLY } else { crc >> 1 };
+            bit += 1;
+        }
+        table[0][i] = crc;
+        i += 1;
+    }
+
+    let mut i = 0;
+    while i < 256 {
+        let mut k = 1;
+        while k < 8 {
+            let prev = table[k - 1][i];
+            table[k][i] = (prev >> 8) ^ table[0][(prev & 0xff) as usize];
+            k += 1;
+        }
+        i += 1;
+    }
+
+    table
+}
+
+// masked_checksum returns the masked CRC-32C of data, as stored in the
+// header of compressed and uncompressed data chunks.
+pub fn masked_checksum(data: &[u8]) -> u32 {
+    let crc = checksum(data);
+    crc.rotate_right(15).wrapping_add(0xa282_ead8)
+}
+
+// checksum returns the CRC-32C of data, using the SSE4.2 crc32 instruction
+// when the CPU supports it.
+pub fn checksum(data: &[u8]) -> u32 {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("sse4.2") {
+            return unsafe { !update_sse42(!0, data) };
+        }
+    }
+    !update_portable(!0, data)
+}
+
+fn update_portable(mut crc: u32, data: &[u8]) -> u32 {
+    let mut words = data.chunks_exact(8);
+
+    for w in &mut words {
+        crc ^= (w[0] as u32) | ((w[1] as u32) << 8) | ((w[2] as u32) << 16) | ((w[3] as u32) << 24);
+        crc = TABLE[7][(crc & 0xff) as usize] ^
+            TABLE[6][((crc >> 8) & 0xff) as usize] ^
+            TABLE[5][((crc >> 16) & 0xff) as usize] ^
+            TABLE[4][(crc >> 24) as usize] ^
+            TABLE[3][w[4] as usize] ^
+            TABLE[2][w[5] as usize] ^
+            TABLE[1][w[6] as usize] ^
+            TABLE[0][w[7] as usize];
+    }
+
+    for &b in words.remainder() {
+        crc = TABLE[0][((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+
+    crc
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse4.2")]
+unsafe fn update_sse42(crc: u32, data: &[u8]) -> u32 {
+    use std::arch::x86_64::{_mm_crc32_u64, _mm_crc32_u8};
+
+    let mut crc = crc as u64;
+    let mut words = data.chunks_exact(8);
+
+    for w in &mut words {
+        let x = u64::from_le_bytes([w[0], w[1], w[2], w[3], w[4], w[5], w[6], w[7]]);
+        crc = _mm_crc32_u64(crc, x);
+    }
+
+    let mut crc = crc as u32;
+    for &b in words.remainder() {
+        crc = _mm_crc32_u8(crc, b);
+    }
+
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use super::{checksum, update_portable};
+
+    // Check values from RFC 3720, Appendix B.4, plus the customary "123456789".
+    const VECTORS: [(&[u8], u32); 4] = [
+        (b"", 0x0000_0000),
+        (b"123456789", 0xe306_9283),
+        (&[0x00; 32], 0x8a91_36aa),
+        (&[0xff; 32], 0x62a8_ab43),
+    ];
+
+    #[test]
+    fn should_match_known_vectors() {
+        for &(data, expected) in VECTORS.iter() {
+            assert_eq!(checksum(data), expected);
+            assert_eq!(!update_portable(!0, data), expected);
+        }
+    }
+
+    #[test]
+    fn should_agree_across_lengths_and_alignments() {
+        let data: Vec<u8> = (0..1024u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+        for start in 0..9 {
+            for end in start..data.len() {
+                let slice = &data[start..end];
+                assert_eq!(checksum(slice), !update_portable(!0, slice));
+            }
+        }
+    }
+}
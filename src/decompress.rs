@@ -1,320 +1,892 @@
-extern crate byteorder;
-extern crate crc;
-
-use std::result::*;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
 use std::io;
-use std::io::{BufReader, BufRead, ErrorKind, Read, Seek, SeekFrom, Error};
-use self::byteorder::{ByteOrder, ReadBytesExt, LittleEndian};
-use self::crc::{crc32, Hasher32};
+#[cfg(feature = "std")]
+use std::io::{BufReader, BufRead, ErrorKind, Read};
 
-use definitions::*;
-use compress::{max_compressed_len};
+use crate::definitions::*;
+#[cfg(feature = "std")]
+use crate::crc32c::masked_checksum;
+use crate::error::{Error, Result};
 
 // The Max Encoded Length of the Max Chunk of 65536 bytes
+#[cfg(feature = "std")]
 const MAX_BUFFER_SIZE: usize = 76_490;
 
+// Where the Decompressor is within the chunk it is currently reading. Progress
+// is kept across calls to `read`, so an inner reader that stops part way
+// through a chunk (short reads, `Interrupted`, `WouldBlock`) loses nothing.
+#[cfg(feature = "std")]
+enum State {
+	// Reading a chunk header; holds how many of its 4 bytes have been read.
+	Header(usize),
+	// Reading a chunk body of the given type into `body`; holds how many of
+	// its bytes have been read.
+	Body(u8, usize),
+	// Discarding a skippable chunk; holds how many bytes are left to discard.
+	Skip(usize),
+}
+
+#[cfg(feature = "std")]
 pub struct Decompressor<R: Read> {
 	inner: BufReader<R>,
+	chunks: ChunkDecoder,
+}
+
+#[cfg(feature = "std")]
+impl <R: Read> Decompressor<R> {
+
+	pub fn new(inner: R) -> Decompressor<R> {
+		Decompressor::with_limit(inner, u64::MAX)
+	}
+
+	// Creates a Decompressor which decodes at most limit bytes in total.
+	// Reading fails with Error::LimitExceeded once a chunk would take the
+	// output past the limit; the bytes before that chunk are still returned.
+	pub fn with_limit(inner: R, limit: u64) -> Decompressor<R> {
+		Decompressor {
+			inner: BufReader::new(inner),
+			chunks: ChunkDecoder::new(limit),
+		}
+	}
+}
+
+// ChunkDecoder reads and decodes the framing format, a chunk at a time. It
+// does no I/O itself: bytes come from the read function passed to fill, so
+// that the blocking and async readers can share it.
+#[cfg(feature = "std")]
+pub(crate) struct ChunkDecoder {
+	state: State,
+	header: [u8; CHUNK_HEADER_SIZE as usize],
+	// body holds the chunk body being read, at most one maximum-size
+	// compressed chunk.
+	body: Vec<u8>,
 	decoded: [u8; MAX_UNCOMPRESSED_CHUNK_LEN as usize],
-	// buf: Vec<u8>,
 	// decoded[i:j] contains decoded bytes that have not yet been passed on.
 	i: usize,
 	j: usize,
+	// report counts the chunks read so far, which also locates the chunk
+	// being read: it has index report.chunks and starts at offset
+	// report.compressed_bytes. The decoded bytes, report.uncompressed_bytes,
+	// may not exceed limit.
+	report: StreamReport,
+	limit: u64,
 	read_header: bool,
 }
 
-impl <R: Read> Decompressor<R> {
+#[cfg(feature = "std")]
+impl ChunkDecoder {
 
-	pub fn new(inner: R) -> Decompressor<R> {
-		Decompressor {
-			inner: BufReader::new(inner),
+	pub(crate) fn new(limit: u64) -> ChunkDecoder {
+		ChunkDecoder {
+			state: State::Header(0),
+			header: [0; CHUNK_HEADER_SIZE as usize],
+			body: Vec::new(),
 			decoded: [0; MAX_UNCOMPRESSED_CHUNK_LEN as usize],
 			i: 0,
 			j: 0,
+			report: StreamReport::default(),
+			limit,
 			read_header: false,
 		}
 	}
-}
 
-// TODO
-// IMPLEMENT BufRead for Decompressor Instead of a Read
-impl <R: Read> Read for Decompressor<R> {
-	// Implement Read
-	// Source (Inner) Buffer into Destination Buffer, returning how many bytes were read.
-	fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+	// The decoded bytes that have not been consumed yet.
+	pub(crate) fn decoded(&self) -> &[u8] {
+		&self.decoded[self.i..self.j]
+	}
 
-		// FOR DEBUGGING 
-		// For now, just Read entire Buffer
-		let fullbuf = try!(self.inner.fill_buf());
+	pub(crate) fn consume(&mut self, amt: usize) {
+		self.i += amt;
+		if self.i > self.j {
+			self.i = self.j;
+		}
+	}
 
-		// Temporary Seek
-		let mut pos: usize = 0;
+	// Reads and decodes chunks with read, which behaves like Read::read, until
+	// decoded data is available. Returns false at a clean end of stream.
+	pub(crate) fn fill<F>(&mut self, mut read: F) -> io::Result<bool>
+		where F: FnMut(&mut [u8]) -> io::Result<usize> {
+		while self.i >= self.j {
+			match self.state {
+
+				State::Header(mut n) => {
+					while n < self.header.len() {
+						let read = match read(&mut self.header[n..]) {
+							Ok(read) => read,
+							Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+							Err(e) => {
+								self.state = State::Header(n);
+								return Err(e);
+							}
+						};
+						if read == 0 {
+							// EOF is only clean between chunks.
+							if n == 0 {
+								return Ok(false);
+							}
+							return Err(Error::UnexpectedEof { offset: self.report.compressed_bytes }.into());
+						}
+						n += read;
+					}
+					// Stay on a bad header, so that every later read fails too.
+					self.state = State::Header(n);
+					self.state = self.begin_chunk()?;
+				},
 
-		loop {
+				State::Body(chunk_type, mut n) => {
+					while n < self.body.len() {
+						let read = match read(&mut self.body[n..]) {
+							Ok(read) => read,
+							Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+							Err(e) => {
+								self.state = State::Body(chunk_type, n);
+								return Err(e);
+							}
+						};
+						if read == 0 {
+							return Err(Error::UnexpectedEof { offset: self.report.compressed_bytes }.into());
+						}
+						n += read;
+					}
+					let offset = self.report.compressed_bytes;
+					let res = decode_chunk(chunk_type, &self.body, &mut self.decoded, offset, self.report.chunks, &mut ChunkSums::default())
+						.and_then(|decoded| {
+							if decoded as u64 > self.limit - self.report.uncompressed_bytes {
+								return Err(Error::LimitExceeded { limit: self.limit });
+							}
+							Ok(decoded)
+						});
+					let decoded = match res {
+						Ok(decoded) => decoded,
+						Err(e) => {
+							// Stay on a bad chunk, so that every later read fails too.
+							self.state = State::Body(chunk_type, n);
+							return Err(e.into());
+						}
+					};
+					self.report.uncompressed_bytes += decoded as u64;
+					self.end_chunk();
+					self.i = 0;
+					self.j = decoded;
+				},
 
-			if self.i < self.j {
-				let mut s = 0; 
-				for (d, l) in dst.iter_mut().zip(self.decoded.split_at(self.i).1.split_at(self.j).0.iter()) {
-			        *d = *l;
-			        s += 1;
-			    }
-			    self.i += s;
-			    return Ok(s)
+				State::Skip(mut left) => {
+					while left > 0 {
+						// Nothing is pending in decoded, so use it as scratch space.
+						let len = if left < self.decoded.len() { left } else { self.decoded.len() };
+						let read = match read(&mut self.decoded[..len]) {
+							Ok(read) => read,
+							Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+							Err(e) => {
+								self.state = State::Skip(left);
+								return Err(e);
+							}
+						};
+						if read == 0 {
+							return Err(Error::UnexpectedEof { offset: self.report.compressed_bytes }.into());
+						}
+						left -= read;
+					}
+					self.end_chunk();
+				},
 			}
+		}
+		Ok(true)
+	}
 
-			// Read Buffer
-			let (header, data) = fullbuf.split_at(pos).1.split_at(4);
+	// Validates a freshly read chunk header and returns the state for reading
+	// its body.
+	fn begin_chunk(&mut self) -> Result<State> {
+		let chunk_type = self.header[0];
+		let chunk_len = self.chunk_len();
 
-			// Buffer has consumed 4 bytes now.
-			// self.inner.consume(4);
-			pos += 4;
+		let decode = check_chunk_header(chunk_type, chunk_len, !self.read_header, self.report.compressed_bytes)?;
+		self.read_header = true;
+		if !decode {
+			return Ok(State::Skip(chunk_len));
+		}
 
-			// Grab Chunk Header
-			if header.len() < 4 {
-				return Err(Error::new(ErrorKind::InvalidInput, "snappy: corrupt input"))
-			}
+		self.body.resize(chunk_len, 0);
+		Ok(State::Body(chunk_type, 0))
+	}
 
-			// Read Chunk Type
-			let chunk_type = header[0];
-			if !self.read_header {
-				if chunk_type != CHUNK_TYPE_STREAM_IDENTIFIER {
-					return Err(Error::new(ErrorKind::InvalidInput, "snappy: corrupt input"))
-				}
-				self.read_header = true;
+	// Moves past the chunk that has just been read.
+	fn end_chunk(&mut self) {
+		match self.header[0] {
+			CHUNK_TYPE_COMPRESSED_DATA => self.report.compressed_chunks += 1,
+			CHUNK_TYPE_UNCOMPRESSED_DATA => self.report.uncompressed_chunks += 1,
+			CHUNK_TYPE_STREAM_IDENTIFIER => {},
+			_ => self.report.skipped_chunks += 1,
+		}
+		self.report.chunks += 1;
+		self.report.compressed_bytes += (CHUNK_HEADER_SIZE as usize + self.chunk_len()) as u64;
+		self.state = State::Header(0);
+	}
+
+	// The length of the current chunk, from its header.
+	fn chunk_len(&self) -> usize {
+		self.header[1] as usize | ((self.header[2] as usize) << 8) | ((self.header[3] as usize) << 16)
+	}
+}
+
+// check_chunk_header checks the header of the chunk at offset, which is the
+// first of the stream if first is set. It returns whether the chunk's body is
+// to be read and checked by decode_chunk, rather than skipped.
+#[cfg(feature = "std")]
+pub(crate) fn check_chunk_header(chunk_type: u8, chunk_len: usize, first: bool, offset: u64) -> Result<bool> {
+	if first && chunk_type != CHUNK_TYPE_STREAM_IDENTIFIER {
+		return Err(Error::Corrupt { offset })
+	}
+
+	// The chunk types are specified at
+	// https://github.com/google/snappy/blob/master/framing_format.txt
+	let max_len = match chunk_type {
+		// Section 4.2. Compressed data (chunk type 0x00).
+		CHUNK_TYPE_COMPRESSED_DATA => CHECK_SUM_SIZE as usize + MAX_BUFFER_SIZE,
+		// Section 4.3. Uncompressed data (chunk type 0x01).
+		CHUNK_TYPE_UNCOMPRESSED_DATA => CHECK_SUM_SIZE as usize + MAX_UNCOMPRESSED_CHUNK_LEN as usize,
+		// Section 4.1. Stream identifier (chunk type 0xff).
+		CHUNK_TYPE_STREAM_IDENTIFIER => MAGIC_BODY.len(),
+		// Section 4.5. Reserved unskippable chunks (chunk types 0x02-0x7f).
+		0x02..=0x7f => {
+			return Err(Error::UnsupportedChunk(chunk_type))
+		},
+		// Section 4.4 Padding (chunk type 0xfe).
+		// Section 4.6. Reserved skippable chunks (chunk types 0x80-0xfd).
+		CHUNK_TYPE_PADDING | 0x80..=0xfd => return Ok(false),
+	};
+	if chunk_len > max_len {
+		return Err(Error::Corrupt { offset })
+	}
+	Ok(true)
+}
+
+// ChunkSums is what decode_chunk learns of a data chunk as it goes, so that a
+// chunk which fails to decode can still be described.
+#[cfg(feature = "std")]
+#[derive(Default)]
+pub(crate) struct ChunkSums {
+	// The checksum stored in the chunk, and the one computed from its decoded
+	// data.
+	pub(crate) stored: Option<u32>,
+	pub(crate) actual: Option<u32>,
+	// The length of the decoded data, as given by the block header of a
+	// compressed chunk.
+	pub(crate) decoded_len: Option<usize>,
+}
+
+// decode_chunk checks and decodes the body of a non-skippable chunk, whose
+// header check_chunk_header has passed, into decoded, returning the number of
+// decoded bytes. offset and index locate the chunk within the stream, for
+// error reporting.
+#[cfg(feature = "std")]
+pub(crate) fn decode_chunk(chunk_type: u8, body: &[u8], decoded: &mut [u8], offset: u64, index: u64, sums: &mut ChunkSums) -> Result<usize> {
+	let body_offset = offset + CHUNK_HEADER_SIZE as u64;
+	let data_offset = body_offset + CHECK_SUM_SIZE as u64;
+
+	match chunk_type {
+
+		CHUNK_TYPE_COMPRESSED_DATA => {
+			if body.len() < CHECK_SUM_SIZE as usize {
+				return Err(Error::Corrupt { offset: body_offset })
 			}
 
-			// Read Chunk Length
-			let chunk_len = header[1] as usize | ((header[2] as usize) << 8) | ((header[3] as usize) << 16);
-			if chunk_len > data.len() {
-				return Err(Error::new(ErrorKind::InvalidInput, "snappy: unsupported input"))
+			// Read Checksum
+			let check_sum = read_checksum(body);
+			sums.stored = Some(check_sum);
+
+			// Set Chunk's Data Buffer Slice
+			let data_buf = &body[CHECK_SUM_SIZE as usize..];
+
+			// Check Decompressed Length
+			let (n, _) = decompressed_len(data_buf).map_err(|e| in_chunk(e, data_offset))?;
+			sums.decoded_len = Some(n);
+			if n > decoded.len() {
+				return Err(Error::Corrupt { offset: data_offset })
 			}
 
-			// The chunk types are specified at
-			// https://github.com/google/snappy/blob/master/framing_format.txt
-			match chunk_type {
+			// Decompress
+			decompress(decoded, data_buf).map_err(|e| in_chunk(e, data_offset))?;
 
-				// Section 4.2. Compressed data (chunk type 0x00).
-				CHUNK_TYPE_COMPRESSED_DATA => {
-					if chunk_len < (CHECK_SUM_SIZE as usize) {
-						return Err(Error::new(ErrorKind::InvalidInput, "snappy: corrupt input"))
-					}
-					// Setup Buffer Slice with Chunk Length
-					let chunk_buf: &[u8] = data.split_at(chunk_len).0;
-
-					// Read Checksum
-					let check_sum = chunk_buf[0] as u32 | ((chunk_buf[1] as u32) << 8) | ((chunk_buf[2] as u32) << 16) | ((chunk_buf[3] as u32) << 24);
-					
-					// Set Chunk's Data Buffer Slice
-					let data_buf = chunk_buf.split_at(CHECK_SUM_SIZE as usize).1;
-
-					// Check Decompressed Length
-					let n = decompressed_len(data_buf.as_ref()) as usize;
-					if n > self.decoded.len() {
-						return Err(Error::new(ErrorKind::InvalidInput, "snappy: corrupt input"))
-					}
+			// Check Checksum
+			check_checksum(check_sum, &decoded[..n], index, sums)?;
+			Ok(n)
+		},
 
-					// Decompress
-					try!(decompress(self.decoded.as_mut(), data_buf));
+		CHUNK_TYPE_UNCOMPRESSED_DATA => {
+			if body.len() < CHECK_SUM_SIZE as usize {
+				return Err(Error::Corrupt { offset: body_offset })
+			}
 
-					// Check Checksum
-					if crc32::checksum_ieee(self.decoded.split_at(n).0) != check_sum {
-						return Err(Error::new(ErrorKind::InvalidInput, "snappy: corrupt input"))
-					}
+			// Read Checksum
+			let check_sum = read_checksum(body);
+			sums.stored = Some(check_sum);
+
+			// Copy into decoded
+			let data_buf = &body[CHECK_SUM_SIZE as usize..];
+			let n = data_buf.len();
+			sums.decoded_len = Some(n);
+			decoded[..n].copy_from_slice(data_buf);
+
+			// Check Checksum
+			check_checksum(check_sum, &decoded[..n], index, sums)?;
+			Ok(n)
+		},
+
+		CHUNK_TYPE_STREAM_IDENTIFIER => {
+			// Check Written Buffer
+			if body != MAGIC_BODY {
+				return Err(Error::Corrupt { offset: body_offset })
+			}
+			Ok(0)
+		},
 
-					// Update Buffer
-					// self.inner.consume(chunk_len);
-					pos += chunk_len;
-					self.i = 0;
-					self.j = n;
-					continue;
-				},
-				// Section 4.3. Uncompressed data (chunk type 0x01).
-				CHUNK_TYPE_UNCOMPRESSED_DATA => {
-					if chunk_len < (CHECK_SUM_SIZE as usize) {
-						return Err(Error::new(ErrorKind::InvalidInput, "snappy: corrupt input"))
-					}
+		_ => unreachable!()
+	}
+}
 
-					// Setup Buffer Slice with Chunk Length
-					let chunk_buf = data.split_at(chunk_len).0;
+// in_chunk converts an error from decoding a block, which starts at
+// data_offset in the stream, to refer to the stream.
+#[cfg(feature = "std")]
+fn in_chunk(err: Error, data_offset: u64) -> Error {
+	match err {
+		Error::Corrupt { offset } => Error::Corrupt { offset: data_offset + offset },
+		_ => Error::Corrupt { offset: data_offset },
+	}
+}
 
-					// Read Checksum
-					let check_sum = chunk_buf[0] as u32 | ((chunk_buf[1] as u32) << 8) | ((chunk_buf[2] as u32) << 16) | ((chunk_buf[3] as u32) << 24);
-					
-					// Read Directly into self.decoded
-					let n = chunk_len - (CHECK_SUM_SIZE as usize);
+// check_checksum compares the checksum stored for the chunk at index with the
+// checksum of its decoded data, which it records in sums.
+#[cfg(feature = "std")]
+fn check_checksum(expected: u32, decoded: &[u8], index: u64, sums: &mut ChunkSums) -> Result<()> {
+	let actual = masked_checksum(decoded);
+	sums.actual = Some(actual);
+	if actual != expected {
+		return Err(Error::ChecksumMismatch { expected, actual, chunk_index: index })
+	}
+	Ok(())
+}
 
-					let mut data_buf = chunk_buf.split_at(CHECK_SUM_SIZE as usize).1;
+// read_checksum reads the little-endian checksum that starts a data chunk.
+#[cfg(feature = "std")]
+fn read_checksum(body: &[u8]) -> u32 {
+	body[0] as u32 | ((body[1] as u32) << 8) | ((body[2] as u32) << 16) | ((body[3] as u32) << 24)
+}
 
-					// Read by Cloning into self.decoded Buffer
-					data_buf.read(self.decoded.split_at_mut(n).0);
+#[cfg(feature = "std")]
+impl <R: Read> Read for Decompressor<R> {
+	// Implement Read
+	// Source (Inner) Buffer into Destination Buffer, returning how many bytes were read.
+	fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+		let n = {
+			let decoded = self.fill_buf()?;
+			let n = if dst.len() < decoded.len() { dst.len() } else { decoded.len() };
+			dst[..n].copy_from_slice(&decoded[..n]);
+			n
+		};
+		self.consume(n);
+		Ok(n)
+	}
+}
 
-					// Check Checksum
-					if crc32::checksum_ieee(self.decoded.split_at(n).0) != check_sum {
-						return Err(Error::new(ErrorKind::InvalidInput, "snappy: corrupt input"))
-					}
+// Hands out decoded bytes straight from the internal buffer, a chunk at a time.
+#[cfg(feature = "std")]
+impl <R: Read> BufRead for Decompressor<R> {
+	fn fill_buf(&mut self) -> io::Result<&[u8]> {
+		let inner = &mut self.inner;
+		self.chunks.fill(|buf| inner.read(buf))?;
+		Ok(self.chunks.decoded())
+	}
 
-					// self.inner.consume(chunk_len);
-					pos += chunk_len;
-					self.i = 0;
-					self.j = n;
-					continue;
-				},
-				// Section 4.1. Stream identifier (chunk type 0xff).
-				CHUNK_TYPE_STREAM_IDENTIFIER => {
-					if chunk_len != MAGIC_BODY.len() {
-						return Err(Error::new(ErrorKind::InvalidInput, "snappy: corrupt input"))
-					}
+	fn consume(&mut self, amt: usize) {
+		self.chunks.consume(amt);
+	}
+}
 
-					// Setup Buffer Slice with Magic Body Chunk Length
-					let chunk_buf = data.split_at(chunk_len).0;
 
-					// Check Written Buffer
-					if chunk_buf != MAGIC_BODY {
-						return Err(Error::new(ErrorKind::InvalidInput, "snappy: corrupt input"))
-					}
+// StreamReport summarizes a framed stream, as checked by verify_stream.
+#[cfg(feature = "std")]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StreamReport {
+	// The number of chunks read, the stream identifier included, and how
+	// many of them were compressed data, uncompressed data, or skippable
+	// (padding and reserved) chunks.
+	pub chunks: u64,
+	pub compressed_chunks: u64,
+	pub uncompressed_chunks: u64,
+	pub skipped_chunks: u64,
+	// The length of those chunks in the stream, and of the data they decode
+	// to.
+	pub compressed_bytes: u64,
+	pub uncompressed_bytes: u64,
+	// The stream offset of the first bad chunk and what is wrong with it.
+	// Checking stops there, so the counts above cover the chunks before it.
+	pub bad_chunk_offset: Option<u64>,
+	pub error: Option<Error>,
+}
 
-					// self.inner.consume(chunk_len);
-					pos += chunk_len;
-					continue;
-				},
+#[cfg(feature = "std")]
+impl StreamReport {
 
-				// Section 4.5. Reserved unskippable chunks (chunk types 0x02-0x7f).
-				0x02...0x7f => {
-					return Err(Error::new(ErrorKind::InvalidInput, "snappy: unsupported input"))
-				},
+	// Returns whether the whole stream checked out.
+	pub fn is_ok(&self) -> bool {
+		self.error.is_none()
+	}
+}
 
-				// Section 4.4 Padding (chunk type 0xfe).
-				// Section 4.6. Reserved skippable chunks (chunk types 0x80-0xfd).
-				_ => {
-					pos += chunk_len;
-					// self.inner.consume(chunk_len);
-				}
+// verify_stream checks the framed stream read from r: the stream identifier,
+// and every chunk's length and checksum. Chunks are decoded one at a time to
+// check their checksums, but the decoded data is discarded, so memory use
+// stays bounded whatever the length of the stream.
+//
+// A bad stream is not an error; it is described by the report. Errors
+// reading from r are returned as they are.
+#[cfg(feature = "std")]
+pub fn verify_stream<R: Read>(r: R) -> io::Result<StreamReport> {
+	let mut inner = BufReader::new(r);
+	let mut d = ChunkDecoder::new(u64::MAX);
+	loop {
+		match d.fill(|buf| inner.read(buf)) {
+			Ok(true) => d.i = d.j,
+			Ok(false) => break,
+			Err(e) => {
+				let err = match e.get_ref().and_then(|e| e.downcast_ref::<Error>()) {
+					Some(err) => err.clone(),
+					None => return Err(e),
+				};
+				d.report.bad_chunk_offset = Some(d.report.compressed_bytes);
+				d.report.error = Some(err);
+				break;
 			}
-
 		}
 	}
+	Ok(d.report)
 }
 
-
-// decompressed_len returns the length of the decoded block.
-pub fn decompressed_len(src: &[u8]) -> u64 {
-	LittleEndian::read_u64(src)
+// decompressed_len returns the length of the decoded block and the number of
+// bytes that the length header occupied.
+pub fn decompressed_len(src: &[u8]) -> Result<(usize, usize)> {
+	// The header is an unsigned varint of at most 32 bits, so it never
+	// spans more than 5 bytes.
+	let mut v: u64 = 0;
+	for (i, &b) in src.iter().take(5).enumerate() {
+		v |= ((b & 0x7f) as u64) << (7 * i);
+		if b & 0x80 == 0 {
+			if v > 0xffff_ffff {
+				return Err(Error::Corrupt { offset: 0 });
+			}
+			return Ok((v as usize, i + 1));
+		}
+	}
+	Err(Error::Corrupt { offset: 0 })
 }
 
 // Decompress reads the decoded form of src into dst and returns the length
 // read.
 // Returns an error if dst was not large enough to hold the entire decoded
 // block.
-pub fn decompress(dst: &mut [u8], src: &[u8]) -> io::Result<usize> {
-	// TODO: Handle Error
-	let dLen = decompressed_len(src) as usize;
-	// TODO FIX!!
-	// For now, always assume 8 bytes used for src header length
-	let mut s: usize = 8;
+pub fn decompress(dst: &mut [u8], src: &[u8]) -> Result<usize> {
+	Decoder::new().decompress(dst, src)
+}
+
+// decompress_to_vec returns the decoded form of src as a new Vec.
+pub fn decompress_to_vec(src: &[u8]) -> Result<Vec<u8>> {
+	Decoder::new().decompress_to_vec(src)
+}
+
+// decompress_into appends the decoded form of src to dst and returns the
+// length appended. dst grows by exactly the decoded length, as read from the
+// block's header.
+pub fn decompress_into(src: &[u8], dst: &mut Vec<u8>) -> Result<usize> {
+	Decoder::new().decompress_into(src, dst)
+}
+
+// validate checks that src is a well-formed block without decoding it, and
+// returns its decoded length.
+pub fn validate(src: &[u8]) -> Result<usize> {
+	Decoder::new().validate(src)
+}
+
+// tokens returns an iterator over the literal and copy tags of the block src,
+// in order, without decoding it.
+pub fn tokens(src: &[u8]) -> Tokens<'_> {
+	Decoder::new().tokens(src)
+}
+
+// Tag is a parsed literal or copy tag.
+enum Tag {
+	// length bytes are to be copied from src[start:].
+	Literal { start: usize, length: usize },
+	// length bytes are to be copied from offset bytes back in the output.
+	Copy { offset: usize, length: usize },
+}
+
+// CopyTag is the kind of tag that encodes a copy, which bounds its offset and
+// length.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CopyTag {
+	// A 2 byte tag: length 4 to 11, offset below 2048.
+	Copy1,
+	// A 3 byte tag: length 1 to 64, offset below 65536.
+	Copy2,
+	// A 5 byte tag: length 1 to 64, any 32 bit offset.
+	Copy4,
+}
+
+// Token is a literal or copy tag of a block, as yielded by Tokens.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Token {
+	// len bytes of the block, starting at offset, are copied to the output.
+	Literal { offset: usize, len: usize },
+	// len bytes are copied from offset bytes back in the output.
+	Copy { tag: CopyTag, offset: usize, len: usize },
+}
+
+// Tokens is the iterator returned by tokens and Decoder::tokens. Each tag is
+// checked as validate checks it; the first bad one yields an error, which
+// ends the iteration.
+pub struct Tokens<'a> {
+	decoder: Decoder,
+	src: &'a [u8],
+	// The position of the next tag in src, and the length decoded so far.
+	s: usize,
+	d: usize,
+	// The decoded length from the block's header, or the error reading it,
+	// which is yielded first.
+	d_len: Result<usize>,
+	done: bool,
+}
+
+impl <'a> Tokens<'a> {
+
+	// Returns the decoded length claimed by the block's header, if it could
+	// be read.
+	pub fn decoded_len(&self) -> Option<usize> {
+		self.d_len.as_ref().ok().copied()
+	}
+
+	// Reads the next tag and checks it against what has been decoded so far.
+	fn next_token(&mut self, d_len: usize) -> Result<Token> {
+		let tag = self.s;
+		let kind = self.src[tag] & 0x03;
+		let token = match self.decoder.read_tag(self.src, &mut self.s)? {
+			Tag::Literal { start, length } => {
+				if length > d_len - self.d {
+					return Err(Error::Corrupt { offset: tag as u64 });
+				}
+				Token::Literal { offset: start, len: length }
+			},
+			Tag::Copy { offset, length } => {
+				if offset == 0 || offset > self.d || length > d_len - self.d {
+					return Err(Error::Corrupt { offset: tag as u64 });
+				}
+				let tag = match kind {
+					TAG_COPY_1 => CopyTag::Copy1,
+					TAG_COPY_2 => CopyTag::Copy2,
+					_ => CopyTag::Copy4,
+				};
+				Token::Copy { tag, offset, len: length }
+			},
+		};
+		self.d += match token {
+			Token::Literal { len, .. } | Token::Copy { len, .. } => len,
+		};
+		Ok(token)
+	}
+}
+
+impl <'a> Iterator for Tokens<'a> {
+	type Item = Result<Token>;
+
+	fn next(&mut self) -> Option<Result<Token>> {
+		if self.done {
+			return None;
+		}
+		let d_len = match self.d_len {
+			Ok(d_len) => d_len,
+			Err(ref e) => {
+				self.done = true;
+				return Some(Err(e.clone()));
+			},
+		};
+
+		let done = self.s >= self.src.len() || (self.d == d_len && !self.decoder.strict);
+		let res = if !done {
+			self.next_token(d_len)
+		} else if self.d != d_len {
+			Err(Error::Corrupt { offset: self.src.len() as u64 })
+		} else {
+			self.done = true;
+			return None;
+		};
+		self.done = res.is_err();
+		Some(res)
+	}
+}
+
+// Decoder decompresses blocks under a fixed set of validation options, so
+// that untrusted input is always decoded under the same limits. It is
+// configured once and can be reused, and shared, freely.
+//
+// Decoder::new() accepts everything the free `decompress` function accepts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Decoder {
+	max_len: usize,
+	allow_copy_4: bool,
+	strict: bool,
+}
+
+impl Decoder {
+
+	pub fn new() -> Decoder {
+		Decoder {
+			max_len: 0xffff_ffff,
+			allow_copy_4: true,
+			strict: true,
+		}
+	}
+
+	// Sets the largest decompressed length to accept. Blocks whose header
+	// claims more are rejected with Error::LimitExceeded before any output
+	// is produced.
+	pub fn max_len(mut self, max_len: usize) -> Decoder {
+		self.max_len = max_len;
+		self
+	}
+
+	// Sets whether COPY_4 tags are accepted. Encoders that work in 64 KiB
+	// fragments never emit them, so refusing them may be useful to catch
+	// unexpected input.
+	pub fn allow_copy_4(mut self, allow: bool) -> Decoder {
+		self.allow_copy_4 = allow;
+		self
+	}
+
+	// Sets whether bytes left over once the block is fully decoded are an
+	// error (strict, the default) or ignored (lenient).
+	pub fn strict(mut self, strict: bool) -> Decoder {
+		self.strict = strict;
+		self
+	}
+
+	// decompressed_len returns the length of the decoded block and the number
+	// of bytes that the length header occupied, checking the length against
+	// the limit.
+	pub fn decompressed_len(&self, src: &[u8]) -> Result<(usize, usize)> {
+		let (d_len, header_len) = decompressed_len(src)?;
+		if d_len > self.max_len {
+			return Err(Error::LimitExceeded { limit: self.max_len as u64 });
+		}
+		Ok((d_len, header_len))
+	}
+
+	// Returns the decoded length of src, for sizing a buffer to decode it
+	// into. It checks that src has enough tags to decode to that length, so
+	// that a bogus header can't make the caller allocate far more than src
+	// could ever fill: no tag decodes to more than 64 bytes, and those that
+	// do take at least 3 bytes.
+	pub(crate) fn plausible_len(&self, src: &[u8]) -> Result<usize> {
+		let (d_len, header_len) = self.decompressed_len(src)?;
+		if d_len > (src.len() - header_len) / 3 * 64 + 64 {
+			return Err(Error::Corrupt { offset: src.len() as u64 });
+		}
+		Ok(d_len)
+	}
+
+	// Decompress reads the decoded form of src into dst and returns the
+	// length read.
+	// Returns an error if dst was not large enough to hold the entire decoded
+	// block.
+	pub fn decompress(&self, dst: &mut [u8], src: &[u8]) -> Result<usize> {
+		let (d_len, header_len) = self.decompressed_len(src)?;
+		let mut s: usize = header_len;
+
+		if dst.len() < d_len {
+			return Err(Error::BufferTooSmall { needed: d_len });
+		}
+		let dst = &mut dst[..d_len];
+
+		let mut d: usize = 0;
+
+		while s < src.len() {
+			// Once the block is fully decoded, a lenient Decoder ignores
+			// whatever follows.
+			if d == d_len && !self.strict {
+				break;
+			}
+
+			let tag = s;
+			match self.read_tag(src, &mut s)? {
+
+				Tag::Literal { start, length } => {
+					if length > dst.len() - d {
+						return Err(Error::Corrupt { offset: tag as u64 });
+					}
+
+					// Copy src[start:start+length] to dst[d:d+length]
+					dst[d..d + length].copy_from_slice(&src[start..start + length]);
+					d += length;
+				},
+
+				Tag::Copy { offset, length } => {
+					if offset == 0 || offset > d || length > dst.len() - d {
+						return Err(Error::Corrupt { offset: tag as u64 });
+					}
+					// Copy byte by byte, as the source and destination ranges
+					// may overlap when offset < length.
+					let end = d + length;
+					while d < end {
+						dst[d] = dst[d - offset];
+						d += 1;
+					}
+				},
+			}
+		}
+		if d != d_len {
+			return Err(Error::Corrupt { offset: src.len() as u64 });
+		}
+		Ok(d)
+	}
+
+	// validate checks that src is a well-formed block, walking every tag the
+	// same as decompress but without producing any output, and returns its
+	// decoded length.
+	pub fn validate(&self, src: &[u8]) -> Result<usize> {
+		self.tokens(src).try_fold(0, |d, token| match token? {
+			Token::Literal { len, .. } | Token::Copy { len, .. } => Ok(d + len),
+		})
+	}
 
-	if dst.len() < dLen {
-        return Err(Error::new(ErrorKind::InvalidInput, "snappy: destination buffer is too short"));
-    }
+	// tokens returns an iterator over the literal and copy tags of the block
+	// src, in order, checked under this Decoder's options.
+	pub fn tokens<'a>(&self, src: &'a [u8]) -> Tokens<'a> {
+		let (d_len, s) = match self.decompressed_len(src) {
+			Ok((d_len, header_len)) => (Ok(d_len), header_len),
+			Err(e) => (Err(e), 0),
+		};
+		Tokens { decoder: *self, src, s, d: 0, d_len, done: false }
+	}
 
-    let (mut d, mut offset, mut length): (usize, usize, usize) = (0,0,0);
+	// read_tag parses the tag at src[*s:], checking that it lies within src,
+	// and moves *s past it, including past a literal's bytes.
+	fn read_tag(&self, src: &[u8], s: &mut usize) -> Result<Tag> {
+		// The offset of the tag, for error reporting.
+		let tag = *s as u64;
+		let (offset, length): (usize, usize);
 
-    while s < src.len() {
-    	match src[s] & 0x03 {
+		match src[*s] & 0x03 {
 
 			// Parse a Literal Chunk
-    		TAG_LITERAL => {
-    			
-    			let mut x = (src[s] >> 2) as usize;
-    			match x {
-	    			0...59 => s += 1,
-    				60 => {
-    					s += 2;
-    					if s > src.len() {
-					        return Err(Error::new(ErrorKind::InvalidInput, "snappy: corrupt input"));
-    					};
-    					x = src[s-1] as usize;
-    				},
-    				61 => {
-    					s += 3;
-    					if s > src.len() {
-					        return Err(Error::new(ErrorKind::InvalidInput, "snappy: corrupt input"));
-    					};
-    					x = (src[s-2] as usize) | ((src[s-1] as usize) << 8);
-    				},
-    				62 => {
-    					s += 4;
-    					if s > src.len() {
-					        return Err(Error::new(ErrorKind::InvalidInput, "snappy: corrupt input"));
-    					};
-    					x = (src[s-3] as usize) | ((src[s-2] as usize) << 8) | ((src[s-1] as usize) << 16);
-    				},
-    				63 => {
-    					s += 5;
-    					if s > src.len() {
-					        return Err(Error::new(ErrorKind::InvalidInput, "snappy: corrupt input"));
-    					};
-    					x = (src[s-4] as usize) | ((src[s-3] as usize) << 8) | ((src[s-2] as usize) << 16) | ((src[s-1] as usize) << 24);
-    				},
-    				_ => {}
-    			}
-    			length = x.wrapping_add(1);
-
-    			if length as isize <= 0 {
-			        return Err(Error::new(ErrorKind::InvalidInput, "snappy: unsupported literal length"));
-    			}
-    			if length > dst.len() - d || length > src.len() - s {
-			        return Err(Error::new(ErrorKind::InvalidInput, "snappy: corrupt input"));
-    			}
-
-    			// Copy src[s: s+length] to dst[d:0]
-    			for (id, is) in dst.split_at_mut(d).1.iter_mut().zip(src.split_at(s).1.split_at(s+length).0.iter()) {
-			        *id = *is;
-			    }
-			    d += length;
-			    s += length;
-			    continue;
-    		},
-
-    		// Parse a Copy1 Chunk
-    		TAG_COPY_1 => {
-    			s+= 2;
-    			if s > src.len() {
-			        return Err(Error::new(ErrorKind::InvalidInput, "snappy: corrupt input"));
+			TAG_LITERAL => {
+
+				let mut x = (src[*s] >> 2) as usize;
+				match x {
+					0..=59 => *s += 1,
+					60 => {
+						*s += 2;
+						if *s > src.len() {
+							return Err(Error::Corrupt { offset: tag });
+						};
+						x = src[*s-1] as usize;
+					},
+					61 => {
+						*s += 3;
+						if *s > src.len() {
+							return Err(Error::Corrupt { offset: tag });
+						};
+						x = (src[*s-2] as usize) | ((src[*s-1] as usize) << 8);
+					},
+					62 => {
+						*s += 4;
+						if *s > src.len() {
+							return Err(Error::Corrupt { offset: tag });
+						};
+						x = (src[*s-3] as usize) | ((src[*s-2] as usize) << 8) | ((src[*s-1] as usize) << 16);
+					},
+					_ => {
+						*s += 5;
+						if *s > src.len() {
+							return Err(Error::Corrupt { offset: tag });
+						};
+						x = (src[*s-4] as usize) | ((src[*s-3] as usize) << 8) | ((src[*s-2] as usize) << 16) | ((src[*s-1] as usize) << 24);
+					},
+				}
+				let length = x.wrapping_add(1);
+
+				if length == 0 || length > src.len() - *s {
+					return Err(Error::Corrupt { offset: tag });
+				}
+				let start = *s;
+				*s += length;
+				return Ok(Tag::Literal { start, length });
+			},
+
+			// Parse a Copy1 Chunk
+			TAG_COPY_1 => {
+				*s += 2;
+				if *s > src.len() {
+					return Err(Error::Corrupt { offset: tag });
 				};
-				length = 4 + (src[s-2] as usize)>>2&0x7;
-				offset = ((src[s-2] as usize)&0xe0<<3) | (src[s-1] as usize);
-    		},
-
-    		// Parse a Copy2 Chunk
-    		TAG_COPY_2 => {
-    			s+= 3;
-    			if s > src.len() {
-			        return Err(Error::new(ErrorKind::InvalidInput, "snappy: corrupt input"));
+				length = 4 + ((src[*s-2] as usize) >> 2 & 0x7);
+				offset = ((src[*s-2] as usize & 0xe0) << 3) | (src[*s-1] as usize);
+			},
+
+			// Parse a Copy2 Chunk
+			TAG_COPY_2 => {
+				*s += 3;
+				if *s > src.len() {
+					return Err(Error::Corrupt { offset: tag });
 				};
-				length = 1 + (src[s-3] as usize)>>2;
-				offset = (src[s-2] as usize) | ((src[s-1] as usize)<<8);
-    		},
-
-    		// Parse a Copy4
-    		TAG_COPY_4 => {
-		        return Err(Error::new(ErrorKind::InvalidInput, "snappy: unsupported COPY_4 tag"));
-    		},
-
-    		_ => unreachable!()
-    	};
-
-    	let end = d + length;
-    	if offset > d || end > dst.len() {
-	        return Err(Error::new(ErrorKind::InvalidInput, "snappy: corrupt input"));
-	    }
-    	while d < end {
-    		d += 1;
-    		dst[d] = dst[d-offset];
-    	}
-    }
-    if d != dLen {
-        return Err(Error::new(ErrorKind::InvalidInput, "snappy: corrupt input"));
-    }
-    Ok(d)
+				length = 1 + ((src[*s-3] as usize) >> 2);
+				offset = (src[*s-2] as usize) | ((src[*s-1] as usize) << 8);
+			},
+
+			// Parse a Copy4 Chunk
+			TAG_COPY_4 => {
+				if !self.allow_copy_4 {
+					return Err(Error::Corrupt { offset: tag });
+				}
+				*s += 5;
+				if *s > src.len() {
+					return Err(Error::Corrupt { offset: tag });
+				};
+				length = 1 + ((src[*s-5] as usize) >> 2);
+				offset = (src[*s-4] as usize) | ((src[*s-3] as usize) << 8) | ((src[*s-2] as usize) << 16) | ((src[*s-1] as usize) << 24);
+			},
+
+			_ => unreachable!()
+		};
+
+		Ok(Tag::Copy { offset, length })
+	}
+
+	// decompress_to_vec returns the decoded form of src as a new Vec.
+	pub fn decompress_to_vec(&self, src: &[u8]) -> Result<Vec<u8>> {
+		let mut dst = Vec::new();
+		self.decompress_into(src, &mut dst)?;
+		Ok(dst)
+	}
+
+	// decompress_into appends the decoded form of src to dst and returns the
+	// length appended. dst grows by exactly the decoded length, as read from
+	// the block's header; a header claiming more than src could decode to is
+	// rejected before dst grows.
+	pub fn decompress_into(&self, src: &[u8], dst: &mut Vec<u8>) -> Result<usize> {
+		let n = self.plausible_len(src)?;
+		let start = dst.len();
+		dst.reserve_exact(n);
+		dst.resize(start + n, 0);
+		let res = self.decompress(&mut dst[start..], src);
+		if res.is_err() {
+			dst.truncate(start);
+		}
+		res
+	}
 }
 
+impl Default for Decoder {
+	fn default() -> Decoder {
+		Decoder::new()
+	}
+}
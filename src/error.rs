@@ -0,0 +1,68 @@
+use core::error;
+use core::fmt;
+use core::result;
+#[cfg(feature = "std")]
+use std::io;
+
+// Error describes why compressing or decompressing failed.
+//
+// The Read and Write implementations report it wrapped in an io::Error, from
+// which it can be recovered with `get_ref` and `downcast_ref::<Error>`.
+// Without the `std` feature, only the block functions return it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    // The destination buffer is too short; it needs at least `needed` bytes.
+    BufferTooSmall { needed: usize },
+    // The source is too long to be encoded as a single block.
+    TooLarge,
+    // The input is corrupt at `offset`: a byte offset into the block for the
+    // block functions, into the stream for framed data.
+    Corrupt { offset: u64 },
+    // The stream ended part way through the chunk that starts at `offset`.
+    UnexpectedEof { offset: u64 },
+    // A data chunk's stored checksum does not match its decoded contents.
+    // Chunks are counted from 0, the stream identifier included.
+    ChecksumMismatch { expected: u32, actual: u32, chunk_index: u64 },
+    // The stream holds a reserved, unskippable chunk type (0x02-0x7f).
+    UnsupportedChunk(u8),
+    // The decoded output would be longer than the configured limit.
+    LimitExceeded { limit: u64 },
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::BufferTooSmall { needed } =>
+                write!(f, "snappy: destination buffer is too short, need {} bytes", needed),
+            Error::TooLarge =>
+                write!(f, "snappy: source buffer is too long"),
+            Error::Corrupt { offset } =>
+                write!(f, "snappy: corrupt input at offset {}", offset),
+            Error::UnexpectedEof { offset } =>
+                write!(f, "snappy: unexpected EOF in chunk at offset {}", offset),
+            Error::ChecksumMismatch { expected, actual, chunk_index } =>
+                write!(f, "snappy: checksum mismatch in chunk {}: expected {:#010x}, got {:#010x}",
+                    chunk_index, expected, actual),
+            Error::UnsupportedChunk(chunk_type) =>
+                write!(f, "snappy: unsupported chunk type {:#04x}", chunk_type),
+            Error::LimitExceeded { limit } =>
+                write!(f, "snappy: decoded output exceeds the limit of {} bytes", limit),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+#[cfg(feature = "std")]
+impl From<Error> for io::Error {
+    fn from(err: Error) -> io::Error {
+        let kind = match err {
+            Error::BufferTooSmall { .. } | Error::TooLarge => io::ErrorKind::InvalidInput,
+            Error::UnexpectedEof { .. } => io::ErrorKind::UnexpectedEof,
+            _ => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, err)
+    }
+}
@@ -0,0 +1,171 @@
+// Walks a framed stream chunk by chunk, to show how it is laid out. Unlike the
+// Decompressor, it carries on past chunks which fail to decode, so that one
+// bad chunk doesn't hide the rest of the stream.
+
+use std::io;
+use std::io::{BufReader, Read};
+use std::vec::Vec;
+
+use crate::definitions::*;
+use crate::error::Error;
+use crate::decompress::{ChunkSums, check_chunk_header, decode_chunk};
+
+// ChunkInfo describes one chunk of a framed stream.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChunkInfo {
+	// The offset of the chunk within the stream, and its index, counting the
+	// stream identifier.
+	pub offset: u64,
+	pub index: u64,
+	pub chunk_type: u8,
+	// The length of the chunk body, from the chunk header.
+	pub len: usize,
+	// The masked checksum stored in a data chunk, and the one computed from
+	// its decoded data, if it decodes.
+	pub stored_checksum: Option<u32>,
+	pub actual_checksum: Option<u32>,
+	// The length of a data chunk's decoded data, as given by the block header
+	// of a compressed chunk.
+	pub uncompressed_len: Option<usize>,
+	// Why the chunk is bad, if it is.
+	pub error: Option<Error>,
+}
+
+impl ChunkInfo {
+
+	// Returns the name of the chunk's type.
+	pub fn type_name(&self) -> &'static str {
+		chunk_type_name(self.chunk_type)
+	}
+}
+
+// chunk_type_name returns the name of a chunk type, as in the CHUNK_TYPE_*
+// constants, or the range of reserved types that it falls in.
+pub fn chunk_type_name(chunk_type: u8) -> &'static str {
+	match chunk_type {
+		CHUNK_TYPE_COMPRESSED_DATA => "COMPRESSED_DATA",
+		CHUNK_TYPE_UNCOMPRESSED_DATA => "UNCOMPRESSED_DATA",
+		CHUNK_TYPE_PADDING => "PADDING",
+		CHUNK_TYPE_STREAM_IDENTIFIER => "STREAM_IDENTIFIER",
+		0x02..=0x7f => "RESERVED_UNSKIPPABLE",
+		0x80..=0xfd => "RESERVED_SKIPPABLE",
+	}
+}
+
+// inspect_stream returns an iterator over the chunks of the framed stream
+// read from r. Chunks that fail to decode are described, with the reason, and
+// the walk goes on. It ends with an error only if the stream can't be read
+// or ends part way through a chunk.
+pub fn inspect_stream<R: Read>(r: R) -> InspectStream<R> {
+	InspectStream {
+		inner: BufReader::new(r),
+		offset: 0,
+		index: 0,
+		body: Vec::new(),
+		decoded: vec![0; MAX_UNCOMPRESSED_CHUNK_LEN as usize],
+		done: false,
+	}
+}
+
+// InspectStream is the iterator returned by inspect_stream.
+pub struct InspectStream<R: Read> {
+	inner: BufReader<R>,
+	offset: u64,
+	index: u64,
+	body: Vec<u8>,
+	decoded: Vec<u8>,
+	done: bool,
+}
+
+impl <R: Read> InspectStream<R> {
+
+	// Reads the next chunk; returns None at a clean end of stream.
+	fn next_chunk(&mut self) -> io::Result<Option<ChunkInfo>> {
+		let mut header = [0; CHUNK_HEADER_SIZE as usize];
+		match read_full(&mut self.inner, &mut header)? {
+			0 => return Ok(None),
+			n if n < header.len() => return Err(Error::UnexpectedEof { offset: self.offset }.into()),
+			_ => {},
+		}
+
+		let chunk_type = header[0];
+		let len = header[1] as usize | ((header[2] as usize) << 8) | ((header[3] as usize) << 16);
+		let mut info = ChunkInfo {
+			offset: self.offset,
+			index: self.index,
+			chunk_type,
+			len,
+			stored_checksum: None,
+			actual_checksum: None,
+			uncompressed_len: None,
+			error: None,
+		};
+
+		// A chunk with a bad header is described, and skipped over like a
+		// padding chunk, as its length can still be trusted.
+		let decode = match check_chunk_header(chunk_type, len, self.index == 0, self.offset) {
+			Ok(decode) => decode,
+			Err(e) => {
+				info.error = Some(e);
+				false
+			},
+		};
+		if decode {
+			self.body.resize(len, 0);
+			if read_full(&mut self.inner, &mut self.body)? < len {
+				return Err(Error::UnexpectedEof { offset: self.offset }.into());
+			}
+			let mut sums = ChunkSums::default();
+			info.error = decode_chunk(chunk_type, &self.body, &mut self.decoded, self.offset, self.index, &mut sums).err();
+			info.stored_checksum = sums.stored;
+			info.actual_checksum = sums.actual;
+			info.uncompressed_len = sums.decoded_len;
+		} else {
+			let skipped = io::copy(&mut (&mut self.inner).take(len as u64), &mut io::sink())?;
+			if skipped < len as u64 {
+				return Err(Error::UnexpectedEof { offset: self.offset }.into());
+			}
+		}
+
+		self.offset += (CHUNK_HEADER_SIZE as usize + len) as u64;
+		self.index += 1;
+		Ok(Some(info))
+	}
+}
+
+impl <R: Read> Iterator for InspectStream<R> {
+	type Item = io::Result<ChunkInfo>;
+
+	fn next(&mut self) -> Option<io::Result<ChunkInfo>> {
+		if self.done {
+			return None;
+		}
+		let res = self.next_chunk();
+		match res {
+			Ok(Some(info)) => Some(Ok(info)),
+			Ok(None) => {
+				self.done = true;
+				None
+			},
+			Err(e) => {
+				self.done = true;
+				Some(Err(e))
+			},
+		}
+	}
+}
+
+// read_full reads into buf until it is full or the reader is exhausted, and
+// returns how much was read.
+fn read_full<R: Read>(r: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+	let mut n = 0;
+	while n < buf.len() {
+		match r.read(&mut buf[n..]) {
+			Ok(0) => break,
+			Ok(read) => n += read,
+			Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+			Err(e) => return Err(e),
+		}
+	}
+	Ok(n)
+}
@@ -1,5 +1,18 @@
+// The block codec only needs `core` and `alloc`. The framed Compressor and
+// Decompressor, which read and write through `std::io`, need the default
+// `std` feature.
+#![no_std]
+
+#[cfg(feature = "std")]
+#[macro_use]
+extern crate std;
+#[macro_use]
+extern crate alloc;
+
 
 // Definitions
+// Most of these describe the framing format, which needs the `std` feature.
+#[cfg_attr(not(feature = "std"), allow(dead_code))]
 mod definitions {
 
 	//
@@ -25,7 +38,9 @@ mod definitions {
 	// - For l == 2, the offset ranges in [0, 1<<16) and the length in [1, 65).
 	// The length is 1 + m. The offset is the little-endian unsigned integer
 	// denoted by the next 2 bytes.
-	// - For l == 3, this tag is a legacy format that is no longer supported.
+	// - For l == 3, the offset ranges in [0, 1<<32) and the length in [1, 65).
+	// The length is 1 + m. The offset is the little-endian unsigned integer
+	// denoted by the next 4 bytes.
 	//
     pub const TAG_LITERAL: u8 = 0x00;
     pub const TAG_COPY_1: u8 = 0x01;
@@ -47,11 +62,52 @@ mod definitions {
     pub const CHUNK_TYPE_STREAM_IDENTIFIER: u8 = 0xff;
 }
 
+// Errors
+mod error;
+pub use self::error::{Error, Result};
+
+// Framing Format Checksums
+#[cfg(feature = "std")]
+mod crc32c;
+
 // Snappy Compressor
 mod compress;
-pub use self::compress::{Compressor, compress, max_compressed_len};
+pub use self::compress::{CompressStats, CompressionPolicy, Encoder, compress, compress_into, compress_to_vec, compress_with_stats, max_compressed_len};
+#[cfg(feature = "std")]
+pub use self::compress::{Compressor, IntoInnerError, compress_iovec};
 
 // Snappy Decompressor
 mod decompress;
-pub use self::decompress::{Decompressor, decompress, decompressed_len};
+pub use self::decompress::{CopyTag, Decoder, Token, Tokens, decompress, decompress_into, decompress_to_vec, decompressed_len, tokens, validate};
+#[cfg(feature = "std")]
+pub use self::decompress::{Decompressor, StreamReport, verify_stream};
+
+// Framed Stream Inspector
+#[cfg(feature = "std")]
+mod inspect;
+#[cfg(feature = "std")]
+pub use self::inspect::{ChunkInfo, InspectStream, chunk_type_name, inspect_stream};
+
+// Multithreaded Compressor
+#[cfg(feature = "std")]
+mod parallel;
+#[cfg(feature = "std")]
+pub use self::parallel::ParallelCompressor;
+
+// Block Compression with the bytes Crate's Buffers
+#[cfg(feature = "bytes")]
+mod buf;
+#[cfg(feature = "bytes")]
+pub use self::buf::{compress_buf, decompress_buf};
+
+// Async Compressor and Decompressor
+#[cfg(any(feature = "tokio", feature = "futures"))]
+mod async_io;
+#[cfg(any(feature = "tokio", feature = "futures"))]
+pub use self::async_io::{AsyncCompressor, AsyncDecompressor};
 
+// Codec for tokio_util's Framed
+#[cfg(feature = "tokio-util")]
+mod codec;
+#[cfg(feature = "tokio-util")]
+pub use self::codec::{Payload, SnappyCodec};
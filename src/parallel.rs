@@ -0,0 +1,364 @@
+// ParallelCompressor writes the same framing format stream as the Compressor,
+// but compresses its chunks on a pool of worker threads. Chunks are
+// independent, so each worker encodes whole chunks with its own ChunkEncoder,
+// and the results are put back in order before they are written.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::io::Write;
+use std::panic::{self, AssertUnwindSafe};
+use std::string::ToString;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+use std::vec::Vec;
+
+use crate::definitions::*;
+use crate::error::Error;
+use crate::compress::{ChunkEncoder, CompressStats, CompressionPolicy, ChunkWriter, IntoInnerError};
+
+// A chunk of input, numbered by its place in the stream.
+struct Job {
+	seq: u64,
+	src: Vec<u8>,
+}
+
+// A chunk encoded by a worker: its header and body, or why it couldn't be
+// encoded, and the stats of encoding it. The input is handed back to be reused.
+struct Done {
+	seq: u64,
+	src: Vec<u8>,
+	out: io::Result<Vec<u8>>,
+	stats: CompressStats,
+}
+
+// ParallelCompressor writes the framing format, compressing chunks on worker
+// threads. Its output is byte for byte the same as the Compressor's given the
+// same writes, flushes and policy: input is staged into chunks of
+// MAX_UNCOMPRESSED_CHUNK_LEN bytes the same way, a partial chunk is only
+// emitted on flush, finish, or when the ParallelCompressor is dropped, and a
+// chunk the inner writer fails part way through is resumed the same way.
+//
+// At most twice as many chunks as there are workers are in flight at once; a
+// write waits for the oldest of them to be written once that many are.
+pub struct ParallelCompressor<W: Write> {
+	// inner is only None once into_inner has taken it.
+	inner: Option<W>,
+	// buf_src holds input that has not been sent to a worker yet.
+	buf_src: Vec<u8>,
+	// Chunks go to the workers through jobs, and come back through done.
+	// jobs is only None once the workers have been told to stop.
+	jobs: Option<Sender<Job>>,
+	done: Receiver<Done>,
+	workers: Vec<JoinHandle<()>>,
+	// The number of the next chunk to send, and of the next to write.
+	next_sent: u64,
+	next_written: u64,
+	// Chunks which came back before an earlier one.
+	ready: BTreeMap<u64, Done>,
+	max_in_flight: u64,
+	// Input buffers handed back by the workers, for reuse.
+	spare: Vec<Vec<u8>>,
+	stats: CompressStats,
+	chunk_writer: ChunkWriter,
+	// Set once a chunk couldn't be encoded. The stream can't go on without
+	// it, so every later write, flush or into_inner fails with this error.
+	failed: Option<io::Error>,
+}
+
+impl <W: Write> ParallelCompressor<W> {
+
+	// Creates a ParallelCompressor with a worker for each CPU.
+	pub fn new(inner: W) -> ParallelCompressor<W> {
+		ParallelCompressor::with_threads(inner, CompressionPolicy::default(), 0)
+	}
+
+	// Creates a ParallelCompressor, with a worker for each CPU, which applies
+	// policy to decide which chunks to store compressed.
+	pub fn with_policy(inner: W, policy: CompressionPolicy) -> ParallelCompressor<W> {
+		ParallelCompressor::with_threads(inner, policy, 0)
+	}
+
+	// Creates a ParallelCompressor with the given number of workers, or a
+	// worker for each CPU if threads is 0, which applies policy to decide
+	// which chunks to store compressed.
+	pub fn with_threads(inner: W, policy: CompressionPolicy, threads: usize) -> ParallelCompressor<W> {
+		let threads = if threads == 0 {
+			thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+		} else {
+			threads
+		};
+
+		let (jobs, job_queue) = mpsc::channel();
+		let (done_queue, done) = mpsc::channel();
+		let job_queue = Arc::new(Mutex::new(job_queue));
+		let workers = (0..threads).map(|_| {
+			let job_queue = Arc::clone(&job_queue);
+			let done_queue = done_queue.clone();
+			thread::spawn(move || work(policy, &job_queue, &done_queue))
+		}).collect();
+
+		ParallelCompressor {
+			inner: Some(inner),
+			buf_src: Vec::with_capacity(MAX_UNCOMPRESSED_CHUNK_LEN as usize),
+			jobs: Some(jobs),
+			done,
+			workers,
+			next_sent: 0,
+			next_written: 0,
+			ready: BTreeMap::new(),
+			max_in_flight: 2 * threads as u64,
+			spare: Vec::new(),
+			stats: CompressStats::default(),
+			chunk_writer: ChunkWriter::default(),
+			failed: None,
+		}
+	}
+
+	// Gets a reference to the inner writer.
+	pub fn get_ref(&self) -> &W {
+		self.inner.as_ref().unwrap()
+	}
+
+	// Gets a mutable reference to the inner writer. Writing to it directly
+	// will corrupt the framed stream.
+	pub fn get_mut(&mut self) -> &mut W {
+		self.inner.as_mut().unwrap()
+	}
+
+	// Returns what the ParallelCompressor has done so far, as the
+	// Compressor's stats do. Chunks are counted once they are written, so
+	// input staged or still with the workers is not counted.
+	pub fn stats(&self) -> &CompressStats {
+		&self.stats
+	}
+
+	// Emits any staged input, waits for every chunk to be written, flushes,
+	// and returns the inner writer. On error the ParallelCompressor is handed
+	// back, so that the caller may retry.
+	#[allow(clippy::result_large_err)]
+	pub fn into_inner(mut self) -> ::std::result::Result<W, IntoInnerError<ParallelCompressor<W>>> {
+		if let Err(err) = self.flush() {
+			return Err(IntoInnerError { inner: self, err });
+		}
+		Ok(self.inner.take().unwrap())
+	}
+
+	// Finishes the stream: emits any staged input, waits for every chunk to
+	// be written, flushes, and returns the inner writer.
+	pub fn finish(self) -> io::Result<W> {
+		self.into_inner().map_err(|e| e.err)
+	}
+
+	// Takes all of src, sending chunks to the workers as they fill up, and
+	// counts the bytes taken in written. On error, the bytes counted have
+	// still been taken.
+	fn stage_input(&mut self, mut src: &[u8], written: &mut usize) -> io::Result<()> {
+
+		let chunk_len = MAX_UNCOMPRESSED_CHUNK_LEN as usize;
+
+		while !src.is_empty() {
+			let n = ::core::cmp::min(chunk_len - self.buf_src.len(), src.len());
+			self.buf_src.extend_from_slice(&src[..n]);
+			*written += n;
+			src = &src[n..];
+
+			if self.buf_src.len() == chunk_len {
+				self.send_staged()?;
+			}
+		}
+
+		Ok(())
+	}
+
+	// Sends any staged input to the workers as a chunk, first writing out
+	// chunks until there is room for it. On error the input stays staged.
+	fn send_staged(&mut self) -> io::Result<()> {
+		if self.buf_src.is_empty() {
+			return Ok(());
+		}
+		while self.next_sent - self.next_written >= self.max_in_flight {
+			self.write_next()?;
+		}
+
+		let src = self.spare.pop().unwrap_or_else(|| Vec::with_capacity(MAX_UNCOMPRESSED_CHUNK_LEN as usize));
+		let src = ::core::mem::replace(&mut self.buf_src, src);
+		let job = Job { seq: self.next_sent, src };
+		self.jobs.as_ref().unwrap().send(job).map_err(|_| worker_exited())?;
+		self.next_sent += 1;
+		Ok(())
+	}
+
+	// Waits for the next chunk in the stream to be encoded and writes it,
+	// preceded by the stream identifier if it is the first. A chunk that
+	// fails to be written is kept, and writing it again resumes after the
+	// bytes that were already written.
+	fn write_next(&mut self) -> io::Result<()> {
+		self.check_failed()?;
+		let mut done = loop {
+			if let Some(done) = self.ready.remove(&self.next_written) {
+				break done;
+			}
+			let done = self.done.recv().map_err(|_| worker_exited())?;
+			self.ready.insert(done.seq, done);
+		};
+
+		let out = match done.out {
+			Ok(ref out) => out,
+			Err(ref e) => {
+				let err = copy_error(e);
+				self.failed = done.out.err();
+				return Err(err);
+			},
+		};
+
+		let inner = self.inner.as_mut().unwrap();
+		let wrote_header = self.chunk_writer.wrote_header();
+		let res = self.chunk_writer.write(inner, &[out]);
+		if !wrote_header && self.chunk_writer.wrote_header() {
+			self.stats.bytes_out += MAGIC_CHUNK.len() as u64;
+		}
+		if let Err(e) = res {
+			self.ready.insert(done.seq, done);
+			return Err(e);
+		}
+
+		self.next_written += 1;
+		self.stats.add(&done.stats);
+		done.src.clear();
+		self.spare.push(done.src);
+		Ok(())
+	}
+
+	// Emits any staged input and writes every chunk sent to the workers.
+	fn write_all_sent(&mut self) -> io::Result<()> {
+		self.check_failed()?;
+		self.send_staged()?;
+		while self.next_written < self.next_sent {
+			self.write_next()?;
+		}
+		Ok(())
+	}
+
+	// Fails with the error of the chunk that couldn't be encoded, if any.
+	fn check_failed(&self) -> io::Result<()> {
+		match self.failed {
+			Some(ref e) => Err(copy_error(e)),
+			None => Ok(()),
+		}
+	}
+}
+
+impl <W: Write> Write for ParallelCompressor<W> {
+	fn write(&mut self, src: &[u8]) -> io::Result<usize> {
+		self.check_failed()?;
+		let mut written: usize = 0;
+		match self.stage_input(src, &mut written) {
+			Err(e) if written == 0 => Err(e),
+			_ => Ok(written),
+		}
+	}
+
+	// Emits any staged input as a chunk, waits for every chunk to be written,
+	// and flushes the inner writer.
+	fn flush(&mut self) -> io::Result<()> {
+		self.write_all_sent()?;
+		self.inner.as_mut().unwrap().flush()
+	}
+}
+
+// Emit any staged input and write every chunk, then stop the workers. Errors
+// can't be reported from here; call finish to learn of them.
+impl <W: Write> Drop for ParallelCompressor<W> {
+	fn drop(&mut self) {
+		if self.inner.is_some() {
+			let _ = self.write_all_sent();
+		}
+		// The workers stop once the job queue is closed and empty.
+		self.jobs = None;
+		for worker in self.workers.drain(..) {
+			let _ = worker.join();
+		}
+	}
+}
+
+// Encodes the chunks taken from jobs until the queue is closed.
+fn work(policy: CompressionPolicy, jobs: &Mutex<Receiver<Job>>, done: &Sender<Done>) {
+	let mut chunks = ChunkEncoder::new(policy);
+	loop {
+		// The lock is only held while waiting for a job, not while encoding.
+		let job = match jobs.lock() {
+			Ok(queue) => queue.recv(),
+			Err(_) => return,
+		};
+		let Job { seq, src } = match job {
+			Ok(job) => job,
+			Err(_) => return,
+		};
+
+		// A panic while encoding is sent back as an error, so that the chunk
+		// isn't waited for forever.
+		let encoded = panic::catch_unwind(AssertUnwindSafe(|| {
+			chunks.encode(&src).map(|(header, body)| {
+				let mut out = Vec::with_capacity(header.len() + body.len());
+				out.extend_from_slice(header);
+				out.extend_from_slice(body);
+				out
+			})
+		}));
+		let out = match encoded {
+			Ok(out) => out.map_err(io::Error::from),
+			Err(_) => {
+				// The encoder may have been left part way through the chunk.
+				chunks = ChunkEncoder::new(policy);
+				Err(io::Error::other("snappy: compression worker panicked"))
+			},
+		};
+		let stats = chunks.take_stats();
+		if done.send(Done { seq, src, out, stats }).is_err() {
+			return;
+		}
+	}
+}
+
+fn worker_exited() -> io::Error {
+	io::Error::other("snappy: compression worker exited")
+}
+
+// Makes a new io::Error like e, which can't be cloned, keeping the Error it
+// wraps if it has one.
+fn copy_error(e: &io::Error) -> io::Error {
+	match e.get_ref().and_then(|e| e.downcast_ref::<Error>()) {
+		Some(err) => err.clone().into(),
+		None => io::Error::new(e.kind(), e.to_string()),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::{ErrorKind, Write};
+	use std::vec::Vec;
+	use super::{Job, ParallelCompressor};
+	use crate::compress::CompressionPolicy;
+	use crate::error::Error;
+
+	#[test]
+	fn should_keep_failing_once_a_chunk_is_lost() {
+		// A chunk too long for the encoder comes back as an error, the same
+		// way as one whose worker panicked.
+		let mut c = ParallelCompressor::with_threads(Vec::new(), CompressionPolicy::Always, 1);
+		let src: Vec<u8> = (0..200_000u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+		c.jobs.as_ref().unwrap().send(Job { seq: 0, src }).unwrap();
+		c.next_sent = 1;
+
+		for _ in 0..2 {
+			let err = c.flush().unwrap_err();
+			let err = err.get_ref().and_then(|e| e.downcast_ref::<Error>()).cloned();
+			assert!(matches!(err, Some(Error::BufferTooSmall { .. })));
+		}
+		assert_eq!(c.write(b"more").unwrap_err().kind(), ErrorKind::InvalidInput);
+		let err = c.into_inner().unwrap_err();
+		assert_eq!(err.error().kind(), ErrorKind::InvalidInput);
+		assert!(err.into_inner().get_ref().is_empty());
+	}
+}
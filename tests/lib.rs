@@ -1,35 +1,32 @@
 extern crate snappy_rust;
 
-#[macro_use]
-extern crate log;
-
-use std::io::{Write, Read, Cursor, Result};
-use snappy_rust::{Compressor,Decompressor, compress, decompress, max_compressed_len};
+use std::io::{self, BufRead, IoSlice, Write, Read, ErrorKind, Result};
+use snappy_rust::{CompressStats,Compressor,CompressionPolicy,ParallelCompressor,Decoder,Decompressor,Encoder,Error, compress, compress_into, compress_iovec, compress_to_vec, compress_with_stats, decompress, decompress_into, decompress_to_vec, decompressed_len, inspect_stream, max_compressed_len, tokens, validate, verify_stream, CopyTag, Token};
 
 
 fn roundtrip(data: &[u8]) -> bool {
 
 	// Write into Buffer
-	let mut comp = Cursor::new(Vec::<u8>::with_capacity(100));
-	let c = Compressor::new(&mut comp)
-		.write_all(data)
+	let mut c = Compressor::new(Vec::<u8>::with_capacity(100));
+	c.write_all(data)
 		.unwrap_or_else(|err| {
 			panic!("Error at Compress: {:?}", err);
 		});
-
-	// Reset Position
-	comp.set_position(0);
+	let comp = c.finish()
+		.unwrap_or_else(|err| {
+			panic!("Error at Finish: {:?}", err);
+		});
 
 	// Read into Buffer
-	let mut decomp : Vec<u8> = vec![0; data.len()];
-	let d = Decompressor::new(&mut comp)
-		.read(&mut decomp)
+	let mut decomp : Vec<u8> = Vec::with_capacity(data.len());
+	let d = Decompressor::new(&comp[..])
+		.read_to_end(&mut decomp)
 		.unwrap_or_else(|err| {
 			panic!("Error at Decompress: {:?}", err);
 		});
 
 	// Check Result
-	decomp == data
+	d == data.len() && decomp == data
 }
 
 #[test]
@@ -74,9 +71,1493 @@ fn should_do_files(){
 	}
 }
 
+// Blocks produced by the reference implementation for small inputs, for which
+// both encoders find no matches and must emit identical bytes.
+const REFERENCE_BLOCKS: [(&[u8], &[u8]); 6] = [
+	(b"", b"\x00"),
+	(b"a", b"\x01\x00a"),
+	(b"ab", b"\x02\x04ab"),
+	(b"abcd", b"\x04\x0cabcd"),
+	(b"abcde", b"\x05\x10abcde"),
+	(b"The quick red fox jumped over the lazy dog",
+		b"\x2a\xa4The quick red fox jumped over the lazy dog"),
+];
+
+fn block_roundtrip(data: &[u8]) -> bool {
+	let mut comp = vec![0; max_compressed_len(data.len())];
+	let n = compress(&mut comp, data).unwrap();
+
+	let (len, _) = decompressed_len(&comp[..n]).unwrap();
+	let mut decomp = vec![0; len];
+	let d = decompress(&mut decomp, &comp[..n]).unwrap();
+
+	d == data.len() && decomp == data
+}
+
+#[test]
+/// Snappy: Block Length Header is a Varint
+fn should_read_varint_header() {
+	assert_eq!(decompressed_len(b"\x00").unwrap(), (0, 1));
+	assert_eq!(decompressed_len(b"\x7f").unwrap(), (127, 1));
+	assert_eq!(decompressed_len(b"\x80\x01").unwrap(), (128, 2));
+	assert_eq!(decompressed_len(b"\xfe\xff\x7f").unwrap(), (0x1f_fffe, 3));
+	assert_eq!(decompressed_len(b"\xff\xff\xff\xff\x0f").unwrap(), (0xffff_ffff, 5));
+
+	// Truncated, too long and overflowing headers
+	assert!(decompressed_len(b"").is_err());
+	assert!(decompressed_len(b"\x80").is_err());
+	assert!(decompressed_len(b"\x80\x80\x80\x80\x80\x00").is_err());
+	assert!(decompressed_len(b"\xff\xff\xff\xff\x10").is_err());
+}
+
+#[test]
+/// Snappy: Roundtrip Blocks
+fn should_do_block_roundtrip() {
+	assert!(block_roundtrip(b""));
+	assert!(block_roundtrip(b"1111111100000000"));
+	assert!(block_roundtrip(include_bytes!("data/alice29.txt")));
+	assert!(block_roundtrip(include_bytes!("data/plrabn12.txt")));
+}
+
+#[test]
+/// Snappy: Encode Blocks Identically to the Reference Implementation
+fn should_encode_reference_blocks() {
+	for &(data, expected) in REFERENCE_BLOCKS.iter() {
+		let mut comp = vec![0; max_compressed_len(data.len())];
+		let n = compress(&mut comp, data).unwrap();
+		assert_eq!(&comp[..n], expected);
+	}
+}
+
+#[test]
+/// Snappy: Decode Blocks from the Reference Implementation
+fn should_decode_reference_blocks() {
+	for &(expected, block) in REFERENCE_BLOCKS.iter() {
+		let mut decomp = vec![0; expected.len()];
+		assert_eq!(decompress(&mut decomp, block).unwrap(), expected.len());
+		assert_eq!(decomp, expected);
+	}
+
+	// The .rawsnappy files were produced from the test files by the reference
+	// implementation.
+	let test_files: Vec<(&str, &[u8], &[u8])> = vec![
+		("alice29.txt", include_bytes!("data/alice29.txt"), include_bytes!("data/alice29.txt.rawsnappy")),
+		("asyoulik.txt", include_bytes!("data/asyoulik.txt"), include_bytes!("data/asyoulik.txt.rawsnappy")),
+		("lcet10.txt", include_bytes!("data/lcet10.txt"), include_bytes!("data/lcet10.txt.rawsnappy")),
+		("plrabn12.txt", include_bytes!("data/plrabn12.txt"), include_bytes!("data/plrabn12.txt.rawsnappy"))
+	];
+
+	for (label, data, block) in test_files {
+		assert_eq!(decompressed_len(block).unwrap().0, data.len(), "Length Mismatch at File: {:?}", label);
+		let mut decomp = vec![0; data.len()];
+		decompress(&mut decomp, block).unwrap();
+		assert!(decomp == data, "Mismatch at File: {:?}", label);
+	}
+}
+
+#[test]
+/// Snappy: Write Framed Checksums Identically to the Reference Implementation
+fn should_write_reference_checksums() {
+	// Uncompressible input is stored as a single uncompressed data chunk.
+	let expected = b"\xff\x06\x00\x00sNaPpY\x01\x14\x00\x00\x1d\xcc\x0b\x27123456789abcdefg";
+
+	let mut c = Compressor::new(Vec::new());
+	c.write_all(b"123456789abcdefg").unwrap();
+	assert_eq!(&c.finish().unwrap()[..], &expected[..]);
+}
+
+#[test]
+/// Snappy: Verify Checksums of Framed Streams from the Reference Implementation
+fn should_read_reference_checksums() {
+	let data = "Lorem ipsum dolor sit amet, ".repeat(40);
+
+	// A single compressed data chunk holding `data`.
+	let stream = b"\xff\x06\x00\x00sNaPpY\x00\x58\x00\x00\xeb\x69\xbd\x86\
+		\xe0\x08\x6cLorem ipsum dolor sit amet, \
+		\xfe\x1c\x00\xfe\x1c\x00\xfe\x1c\x00\xfe\x1c\x00\xfe\x1c\x00\xfe\x1c\x00\
+		\xfe\x1c\x00\xfe\x1c\x00\xfe\x1c\x00\xfe\x1c\x00\xfe\x1c\x00\xfe\x1c\x00\
+		\xfe\x1c\x00\xfe\x1c\x00\xfe\x1c\x00\xfe\x1c\x00\xfe\x1c\x00\x01\x1c";
+
+	let mut decomp = vec![0; data.len()];
+	Decompressor::new(&stream[..]).read_exact(&mut decomp).unwrap();
+	assert_eq!(decomp, data.as_bytes());
+
+	// Flipping a single checksum bit must be detected.
+	let mut corrupt = stream.to_vec();
+	corrupt[14] ^= 0x01;
+	assert!(Decompressor::new(&corrupt[..]).read(&mut decomp).is_err());
+}
+
+// Pseudo-random, incompressible bytes.
+fn noise(len: usize) -> Vec<u8> {
+	let mut x: u32 = 0x2545_f491;
+	(0..len).map(|_| {
+		x ^= x << 13;
+		x ^= x >> 17;
+		x ^= x << 5;
+		x as u8
+	}).collect()
+}
+
+#[test]
+/// Snappy: Decode COPY_4 Tags
+fn should_decode_copy_4() {
+	let data = noise(70_000);
+
+	// 70_010 bytes: a literal of 70_000 bytes, then a COPY_4 of 10 bytes from
+	// offset 70_000.
+	let mut block = vec![0xfa, 0xa2, 0x04, 62 << 2, 0x6f, 0x11, 0x01];
+	block.extend_from_slice(&data);
+	block.extend_from_slice(&[(10 - 1) << 2 | 0x03, 0x70, 0x11, 0x01, 0x00]);
+
+	let mut decomp = vec![0; 70_010];
+	assert_eq!(decompress(&mut decomp, &block).unwrap(), 70_010);
+	assert_eq!(&decomp[..70_000], &data[..]);
+	assert_eq!(&decomp[70_000..], &data[..10]);
+
+	// A truncated COPY_4 tag and an offset reaching before the block start
+	// are both corrupt.
+	assert!(decompress(&mut decomp, &block[..block.len() - 1]).is_err());
+	let last = block.len() - 2;
+	block[last] = 0x02;
+	assert!(decompress(&mut decomp, &block).is_err());
+}
+
+#[test]
+/// Snappy: Encode Matches Further than 65535 Bytes Back
+fn should_encode_copy_4() {
+	// Two copies of the same 100_000 incompressible bytes: the second half
+	// can only be encoded with COPY_4 tags.
+	let mut data = noise(100_000);
+	data.extend_from_slice(&data.clone());
+
+	let mut comp = vec![0; max_compressed_len(data.len())];
+	let n = compress(&mut comp, &data).unwrap();
+	assert!(n < 110_000, "Far Matches Not Found: {:?}", n);
+
+	let mut decomp = vec![0; data.len()];
+	decompress(&mut decomp, &comp[..n]).unwrap();
+	assert!(decomp == data);
+}
+
+// A reader that hands out at most 3 bytes per call, and is interrupted or
+// blocks on every other call.
+struct Trickle<'a> {
+	data: &'a [u8],
+	calls: usize,
+}
+
+impl<'a> Read for Trickle<'a> {
+	fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+		self.calls += 1;
+		match self.calls % 4 {
+			1 => return Err(io::Error::new(ErrorKind::Interrupted, "interrupted")),
+			3 => return Err(io::Error::new(ErrorKind::WouldBlock, "would block")),
+			_ => {}
+		}
+		let n = if buf.len() < 3 { buf.len() } else { 3 };
+		let n = if self.data.len() < n { self.data.len() } else { n };
+		buf[..n].copy_from_slice(&self.data[..n]);
+		self.data = &self.data[n..];
+		Ok(n)
+	}
+}
+
+#[test]
+/// Snappy: Decode Framed Streams from the Reference Implementation
+fn should_read_reference_streams() {
+	// The .sz files were produced from the test files by the reference
+	// implementation.
+	let test_files: Vec<(&str, &[u8], &[u8])> = vec![
+		("alice29.txt", include_bytes!("data/alice29.txt"), include_bytes!("data/alice29.txt.sz")),
+		("asyoulik.txt", include_bytes!("data/asyoulik.txt"), include_bytes!("data/asyoulik.txt.sz")),
+		("lcet10.txt", include_bytes!("data/lcet10.txt"), include_bytes!("data/lcet10.txt.sz")),
+		("plrabn12.txt", include_bytes!("data/plrabn12.txt"), include_bytes!("data/plrabn12.txt.sz"))
+	];
+
+	for (label, data, stream) in test_files {
+		let mut decomp = Vec::new();
+		Decompressor::new(stream).read_to_end(&mut decomp).unwrap();
+		assert!(decomp == data, "Mismatch at File: {:?}", label);
+
+		// A stream cut short is an error, not a clean EOF.
+		let mut decomp = Vec::new();
+		let err = Decompressor::new(&stream[..stream.len() - 1]).read_to_end(&mut decomp).unwrap_err();
+		assert_eq!(err.kind(), ErrorKind::UnexpectedEof, "Truncation Missed at File: {:?}", label);
+	}
+}
+
+#[test]
+/// Snappy: Decode Streams Delivered in Short, Interrupted Reads
+fn should_resume_short_reads() {
+	let data = include_bytes!("data/alice29.txt");
+	let stream = include_bytes!("data/alice29.txt.sz");
+
+	let mut d = Decompressor::new(Trickle { data: stream, calls: 0 });
+	let mut decomp = Vec::new();
+	let mut buf = [0; 1000];
+	loop {
+		match d.read(&mut buf) {
+			Ok(0) => break,
+			Ok(n) => decomp.extend_from_slice(&buf[..n]),
+			Err(ref e) if e.kind() == ErrorKind::WouldBlock => continue,
+			Err(e) => panic!("Error at Decompress: {:?}", e),
+		}
+	}
+	assert!(decomp[..] == data[..]);
+}
+
+#[test]
+/// Snappy: Decode Streams Much Larger than the Read Buffer
+fn should_do_large_streams() {
+	let data = include_bytes!("data/lcet10.txt");
+
+	// Concatenate the reference stream, whose stream identifier may be
+	// repeated, 16 times over for a 6.5 MiB decoded stream.
+	let stream = include_bytes!("data/lcet10.txt.sz").repeat(16);
+
+	let mut d = Decompressor::new(&stream[..]);
+	let mut buf = vec![0; data.len()];
+	for _ in 0..16 {
+		d.read_exact(&mut buf).unwrap();
+		assert!(buf[..] == data[..]);
+	}
+	assert_eq!(d.read(&mut buf).unwrap(), 0);
+}
+
+#[test]
+/// Snappy: Read Decoded Lines Directly from a Decompressor
+fn should_do_buf_read() {
+	let data = include_bytes!("data/asyoulik.txt");
+	let stream = include_bytes!("data/asyoulik.txt.sz");
+
+	// Lines straddle chunk boundaries.
+	let lines: Vec<Vec<u8>> = Decompressor::new(&stream[..])
+		.split(b'\n')
+		.map(|line| line.unwrap())
+		.collect();
+	let expected: Vec<Vec<u8>> = data.split(|&b| b == b'\n').map(|line| line.to_vec()).collect();
+	assert_eq!(lines.len(), expected.len() - 1);
+	assert!(lines[..] == expected[..lines.len()]);
+
+	// fill_buf hands out whole decoded chunks, and consume moves past them.
+	let mut d = Decompressor::new(&stream[..]);
+	let mut decomp = Vec::new();
+	loop {
+		let n = {
+			let buf = d.fill_buf().unwrap();
+			assert!(buf.len() <= 65536);
+			decomp.extend_from_slice(buf);
+			buf.len()
+		};
+		if n == 0 {
+			break;
+		}
+		d.consume(n);
+	}
+	assert!(decomp[..] == data[..]);
+
+	let mut first = Vec::new();
+	Decompressor::new(&stream[..]).read_until(b'\n', &mut first).unwrap();
+	assert!(data.starts_with(&first));
+	assert_eq!(first.last(), Some(&b'\n'));
+}
+
+// Walks a framed stream, returning the type and length of each chunk.
+fn chunks(mut stream: &[u8]) -> Vec<(u8, usize)> {
+	let mut chunks = Vec::new();
+	while !stream.is_empty() {
+		let len = stream[1] as usize | (stream[2] as usize) << 8 | (stream[3] as usize) << 16;
+		chunks.push((stream[0], len));
+		stream = &stream[4 + len..];
+	}
+	chunks
+}
+
+#[test]
+/// Snappy: Stage Small Writes into Full Chunks
+fn should_stage_small_writes() {
+	let data = include_bytes!("data/alice29.txt");
+
+	let mut comp = Vec::new();
+	{
+		let mut c = Compressor::new(&mut comp);
+		for line in data.split(|&b| b == b'\n') {
+			c.write_all(line).unwrap();
+			c.write_all(b"\n").unwrap();
+		}
+	}
+
+	// 152_090 bytes are 2 full chunks and 1 partial chunk, after the stream
+	// identifier.
+	assert_eq!(chunks(&comp).len(), 4);
+
+	// The loop wrote one newline too many.
+	let mut decomp = Vec::new();
+	Decompressor::new(&comp[..]).read_to_end(&mut decomp).unwrap();
+	assert_eq!(decomp.len(), data.len() + 1);
+	assert!(decomp[..data.len()] == data[..]);
+}
+
+#[test]
+/// Snappy: Emit Partial Chunks on Flush
+fn should_emit_on_flush() {
+	let mut comp = Vec::new();
+	{
+		let mut c = Compressor::new(&mut comp);
+		c.write_all(b"123456789").unwrap();
+		c.write_all(b"abcdefg").unwrap();
+		c.flush().unwrap();
+		c.write_all(b"hijklmnop").unwrap();
+	}
+
+	// The flushed chunk, then the chunk emitted on drop.
+	assert_eq!(chunks(&comp), vec![(0xff, 6), (0x01, 4 + 16), (0x01, 4 + 9)]);
+
+	let mut decomp = Vec::new();
+	Decompressor::new(&comp[..]).read_to_end(&mut decomp).unwrap();
+	assert_eq!(&decomp[..], &b"123456789abcdefghijklmnop"[..]);
+}
+
+// A writer that fails every write until it is told to recover.
+#[derive(Debug)]
+struct Faulty {
+	data: Vec<u8>,
+	fail: bool,
+}
+
+impl Write for Faulty {
+	fn write(&mut self, buf: &[u8]) -> Result<usize> {
+		if self.fail {
+			return Err(io::Error::other("disk full"));
+		}
+		self.data.write(buf)
+	}
+
+	fn flush(&mut self) -> Result<()> {
+		Ok(())
+	}
+}
+
+// Choppy is a writer which takes at most 1000 bytes at a time, and fails
+// every third write.
+struct Choppy {
+	data: Vec<u8>,
+	calls: usize,
+}
+
+impl Write for Choppy {
+	fn write(&mut self, buf: &[u8]) -> Result<usize> {
+		self.calls += 1;
+		if self.calls.is_multiple_of(3) {
+			return Err(io::Error::other("disk full"));
+		}
+		let n = ::std::cmp::min(buf.len(), 1000);
+		self.data.write(&buf[..n])
+	}
+
+	fn flush(&mut self) -> Result<()> {
+		Ok(())
+	}
+}
+
+#[test]
+/// Snappy: Finish a Stream and Take Back the Inner Writer
+fn should_finish_into_inner() {
+	let mut c = Compressor::new(Vec::new());
+	c.write_all(b"123456789abcdefg").unwrap();
+
+	// Nothing reaches the inner writer until the chunk is emitted.
+	assert!(c.get_ref().is_empty());
+
+	let comp = c.finish().unwrap();
+	let mut decomp = Vec::new();
+	Decompressor::new(&comp[..]).read_to_end(&mut decomp).unwrap();
+	assert_eq!(&decomp[..], &b"123456789abcdefg"[..]);
+
+	// An empty stream stays empty.
+	assert!(Compressor::new(Vec::new()).finish().unwrap().is_empty());
+}
+
+#[test]
+/// Snappy: Hand the Compressor Back when Finishing Fails
+fn should_retry_into_inner() {
+	let mut c = Compressor::new(Faulty { data: Vec::new(), fail: true });
+	c.write_all(b"123456789abcdefg").unwrap();
+
+	let err = c.into_inner().unwrap_err();
+	assert_eq!(err.error().kind(), ErrorKind::Other);
+
+	// Once the writer recovers, nothing staged has been lost.
+	let mut c = err.into_inner();
+	c.get_mut().fail = false;
+	let comp = c.finish().unwrap().data;
+
+	let mut decomp = Vec::new();
+	Decompressor::new(&comp[..]).read_to_end(&mut decomp).unwrap();
+	assert_eq!(&decomp[..], &b"123456789abcdefg"[..]);
+
+	// A chunk that was partly written when the writer failed is picked up
+	// where it stopped, not written again from the start.
+	let data = include_bytes!("data/alice29.txt");
+	let mut c = Compressor::new(Choppy { data: Vec::new(), calls: 0 });
+	let mut src = &data[..];
+	while !src.is_empty() {
+		match c.write(src) {
+			Ok(n) => src = &src[n..],
+			Err(e) => assert_eq!(e.kind(), ErrorKind::Other),
+		}
+	}
+	let comp = loop {
+		match c.into_inner() {
+			Ok(w) => break w.data,
+			Err(err) => {
+				assert_eq!(err.error().kind(), ErrorKind::Other);
+				c = err.into_inner();
+			},
+		}
+	};
+
+	let mut serial = Compressor::new(Vec::new());
+	serial.write_all(data).unwrap();
+	assert_eq!(comp, serial.finish().unwrap());
+}
+
+fn compress_with(policy: CompressionPolicy, data: &[u8]) -> Vec<u8> {
+	let mut c = Compressor::with_policy(Vec::new(), policy);
+	c.write_all(data).unwrap();
+	let comp = c.finish().unwrap();
+
+	let mut decomp = Vec::new();
+	Decompressor::new(&comp[..]).read_to_end(&mut decomp).unwrap();
+	assert!(&decomp[..] == data);
+	comp
+}
+
+#[test]
+/// Snappy: Store Chunks Compressed when They Save at Least 12.5%
+fn should_compress_chunks() {
+	let data = include_bytes!("data/lcet10.txt");
+	let comp = compress_with(CompressionPolicy::default(), data);
+	assert!(comp.len() < data.len() * 3 / 5, "Poor Compression: {:?}", comp.len());
+	assert!(chunks(&comp)[1..].iter().all(|&(t, _)| t == 0x00));
+
+	// Incompressible chunks are stored as they are.
+	let data = noise(100_000);
+	let comp = compress_with(CompressionPolicy::default(), &data);
+	assert!(chunks(&comp)[1..].iter().all(|&(t, _)| t == 0x01));
+}
+
+#[test]
+/// Snappy: Apply the Configured Compression Policy
+fn should_apply_compression_policy() {
+	let text = include_bytes!("data/alice29.txt");
+	let data = noise(100_000);
+
+	let comp = compress_with(CompressionPolicy::Never, text);
+	assert!(chunks(&comp)[1..].iter().all(|&(t, _)| t == 0x01));
+
+	let comp = compress_with(CompressionPolicy::Always, &data);
+	assert!(chunks(&comp)[1..].iter().all(|&(t, _)| t == 0x00));
+
+	// alice29.txt shrinks by about 40%, so demanding 90% keeps it stored as is.
+	let comp = compress_with(CompressionPolicy::Threshold(0.9), text);
+	assert!(chunks(&comp)[1..].iter().all(|&(t, _)| t == 0x01));
+	let comp = compress_with(CompressionPolicy::Threshold(0.3), text);
+	assert!(chunks(&comp)[1..].iter().all(|&(t, _)| t == 0x00));
+}
+
+// Decodes a framed stream, returning the snappy Error it fails with.
+fn stream_error(stream: &[u8]) -> Error {
+	let mut decomp = Vec::new();
+	let err = Decompressor::new(stream).read_to_end(&mut decomp).unwrap_err();
+	err.get_ref()
+		.and_then(|e| e.downcast_ref::<Error>())
+		.cloned()
+		.unwrap_or_else(|| panic!("Not a snappy Error: {:?}", err))
+}
+
+#[test]
+/// Snappy: Report Block Errors
+fn should_report_block_errors() {
+	let data = b"The quick red fox jumped over the lazy dog";
+	let mut comp = vec![0; 10];
+	assert_eq!(compress(&mut comp, data), Err(Error::BufferTooSmall { needed: max_compressed_len(data.len()) }));
+
+	let block = b"\x2a\xa4The quick red fox jumped over the lazy dog";
+	let mut decomp = vec![0; 10];
+	assert_eq!(decompress(&mut decomp, block), Err(Error::BufferTooSmall { needed: 42 }));
+
+	// The literal tag at offset 1 runs past the end of the block.
+	let mut decomp = vec![0; 42];
+	assert_eq!(decompress(&mut decomp, &block[..30]), Err(Error::Corrupt { offset: 1 }));
+
+	// A copy from before the start of the block, at offset 7.
+	let block = b"\x0a\x10abcde\x09\x06";
+	assert_eq!(decompress(&mut decomp, block), Err(Error::Corrupt { offset: 7 }));
+}
+
+#[test]
+/// Snappy: Report Stream Errors
+fn should_report_stream_errors() {
+	let mut c = Compressor::new(Vec::new());
+	c.write_all(b"123456789abcdefg").unwrap();
+	let stream = c.finish().unwrap();
+
+	let mut corrupt = stream.clone();
+	corrupt[14] ^= 0x01;
+	match stream_error(&corrupt) {
+		Error::ChecksumMismatch { chunk_index, .. } => assert_eq!(chunk_index, 1),
+		err => panic!("Unexpected Error: {:?}", err),
+	}
+
+	assert_eq!(stream_error(&stream[..20]), Error::UnexpectedEof { offset: 10 });
+	assert_eq!(stream_error(&stream[4..]), Error::Corrupt { offset: 0 });
+
+	let mut unsupported = stream.clone();
+	unsupported[10] = 0x02;
+	assert_eq!(stream_error(&unsupported), Error::UnsupportedChunk(0x02));
+
+	// Errors keep their meaning as io::Errors.
+	let mut decomp = Vec::new();
+	let err = Decompressor::new(&corrupt[..]).read_to_end(&mut decomp).unwrap_err();
+	assert_eq!(err.kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+/// Snappy: Roundtrip Blocks through Vecs
+fn should_do_vec_roundtrip() {
+	let data = include_bytes!("data/asyoulik.txt");
+
+	let comp = compress_to_vec(data);
+	assert_eq!(decompressed_len(&comp).unwrap().0, data.len());
+	assert!(decompress_to_vec(&comp).unwrap()[..] == data[..]);
+
+	for &(data, expected) in REFERENCE_BLOCKS.iter() {
+		assert_eq!(compress_to_vec(data), expected);
+		assert_eq!(decompress_to_vec(expected).unwrap(), data);
+	}
+
+	// A run compresses about as well as anything can, and still decodes, but
+	// a header claiming more than the tags could ever decode to is refused
+	// before anything is allocated.
+	let run = vec![0; 1 << 20];
+	assert!(decompress_to_vec(&compress_to_vec(&run)).unwrap() == run);
+	let bomb = b"\xff\xff\xff\xff\x0f\x00";
+	let mut dst = Vec::new();
+	assert_eq!(decompress_into(bomb, &mut dst), Err(Error::Corrupt { offset: 6 }));
+	assert_eq!(dst.capacity(), 0);
+}
+
+#[test]
+/// Snappy: Append Blocks to Vecs
+fn should_append_to_vecs() {
+	let mut comp = b"prefix".to_vec();
+	let n = compress_into(b"The quick red fox jumped over the lazy dog", &mut comp).unwrap();
+	assert_eq!(n, 44);
+	assert_eq!(&comp[..6], b"prefix");
+	assert_eq!(&comp[6..], REFERENCE_BLOCKS[5].1);
+
+	let mut decomp = b"prefix".to_vec();
+	assert_eq!(decompress_into(&comp[6..], &mut decomp).unwrap(), 42);
+	assert_eq!(&decomp[..], &b"prefixThe quick red fox jumped over the lazy dog"[..]);
+
+	// Nothing is appended on error.
+	assert!(decompress_into(&comp[6..30], &mut decomp).is_err());
+	assert_eq!(decomp.len(), 48);
+}
+
+#[test]
+/// Snappy: Reuse an Encoder Across Blocks
+fn should_reuse_encoder() {
+	let mut encoder = Encoder::new();
+	let mut comp = vec![0; max_compressed_len(200_000)];
+
+	// Alternate between inputs of different sizes, so that stale table
+	// entries from larger blocks linger in smaller ones.
+	let inputs: Vec<&[u8]> = vec![
+		include_bytes!("data/alice29.txt"),
+		b"1111111100000000",
+		&include_bytes!("data/asyoulik.txt")[..1000],
+		include_bytes!("data/alice29.txt"),
+		&include_bytes!("data/alice29.txt")[..100],
+		b"abcd",
+	];
+
+	for _ in 0..3 {
+		for data in inputs.iter() {
+			// The output is the same as a fresh encoder's.
+			let n = encoder.compress(&mut comp, data).unwrap();
+			assert_eq!(&comp[..n], &compress_to_vec(data)[..]);
+			assert!(decompress_to_vec(&comp[..n]).unwrap()[..] == data[..]);
+		}
+	}
+}
+
+#[test]
+/// Snappy: Decode Blocks under Configured Limits
+fn should_apply_decoder_options() {
+	let data = include_bytes!("data/alice29.txt");
+	let block = compress_to_vec(data);
+
+	let decoder = Decoder::new().max_len(data.len());
+	assert!(decoder.decompress_to_vec(&block).unwrap()[..] == data[..]);
+
+	let decoder = Decoder::new().max_len(data.len() - 1);
+	let limit = Some(Error::LimitExceeded { limit: data.len() as u64 - 1 });
+	assert_eq!(decoder.decompress_to_vec(&block).err(), limit);
+	let mut decomp = vec![0; data.len()];
+	assert_eq!(decoder.decompress(&mut decomp, &block).err(), limit);
+
+	// Far copies are only emitted for large blocks.
+	let mut far = noise(100_000);
+	far.extend_from_slice(&far.clone());
+	let block = compress_to_vec(&far);
+	assert!(Decoder::new().decompress_to_vec(&block).is_ok());
+	match Decoder::new().allow_copy_4(false).decompress_to_vec(&block) {
+		Err(Error::Corrupt { .. }) => {},
+		res => panic!("COPY_4 Accepted: {:?}", res.map(|v| v.len())),
+	}
+}
+
+#[test]
+/// Snappy: Handle Trailing Bytes Strictly or Leniently
+fn should_handle_trailing_bytes() {
+	let mut block = b"\x05\x10abcde".to_vec();
+	block.extend_from_slice(b"\x00z");
+
+	assert_eq!(Decoder::new().decompress_to_vec(&block), Err(Error::Corrupt { offset: 7 }));
+	assert_eq!(Decoder::new().strict(false).decompress_to_vec(&block).unwrap(), b"abcde");
+}
+
+#[test]
+/// Snappy: Stop Decoding Streams at the Output Limit
+fn should_limit_stream_output() {
+	let data = include_bytes!("data/lcet10.txt");
+	let stream = include_bytes!("data/lcet10.txt.sz");
+
+	let mut decomp = Vec::new();
+	Decompressor::with_limit(&stream[..], data.len() as u64).read_to_end(&mut decomp).unwrap();
+	assert!(decomp[..] == data[..]);
+
+	// Whole chunks up to the limit are handed out, then every read fails.
+	let mut d = Decompressor::with_limit(&stream[..], 100_000);
+	let mut decomp = Vec::new();
+	let err = d.read_to_end(&mut decomp).unwrap_err();
+	assert_eq!(err.kind(), ErrorKind::InvalidData);
+	assert_eq!(err.get_ref().and_then(|e| e.downcast_ref::<Error>()), Some(&Error::LimitExceeded { limit: 100_000 }));
+	assert_eq!(decomp.len(), 65_536);
+	assert!(decomp[..] == data[..65_536]);
+	assert!(d.read(&mut [0; 100]).is_err());
+}
+
+#[test]
+/// Snappy: Reject Blocks Claiming Huge Lengths Before Allocating
+fn should_reject_block_bombs() {
+	// A 6 byte block claiming to decode to 4 GiB.
+	let bomb = b"\xff\xff\xff\xff\x0f\x00";
+	let decoder = Decoder::new().max_len(1 << 20);
+	assert_eq!(decoder.decompress_to_vec(bomb), Err(Error::LimitExceeded { limit: 1 << 20 }));
+}
+
+#[test]
+/// Snappy: Validate Blocks without Decoding Them
+fn should_validate_blocks() {
+	for &(data, block) in REFERENCE_BLOCKS.iter() {
+		assert_eq!(validate(block), Ok(data.len()));
+	}
+	let data = include_bytes!("data/plrabn12.txt");
+	assert_eq!(validate(include_bytes!("data/plrabn12.txt.rawsnappy")), Ok(data.len()));
+
+	// Short inputs are errors, not panics.
+	assert!(validate(b"").is_err());
+	assert!(validate(b"\x80").is_err());
+	assert!(validate(b"\x01").is_err());
+}
+
+#[test]
+/// Snappy: Validate Exactly the Blocks that Decode
+fn should_validate_like_decompress() {
+	let data = include_bytes!("data/alice29.txt");
+	let block = compress_to_vec(&data[..2000]);
+	let decoder = Decoder::new().max_len(4000);
+
+	for i in 0..block.len() {
+		assert!(decoder.validate(&block[..i]).is_err(), "Truncation Missed at: {:?}", i);
+
+		for &bit in [0x01, 0x04, 0x20, 0x80].iter() {
+			let mut corrupt = block.clone();
+			corrupt[i] ^= bit;
+			assert_eq!(decoder.validate(&corrupt), decoder.decompress_to_vec(&corrupt).map(|v| v.len()),
+				"Disagreement at: {:?}", i);
+		}
+	}
+}
+
+struct Broken;
+
+impl Read for Broken {
+	fn read(&mut self, _: &mut [u8]) -> Result<usize> {
+		Err(io::Error::new(ErrorKind::BrokenPipe, "broken pipe"))
+	}
+}
+
+#[test]
+/// Snappy: Verify Streams Without Keeping the Output
+fn should_verify_streams() {
+	let data = include_bytes!("data/alice29.txt");
+	let stream = include_bytes!("data/alice29.txt.sz");
+	let layout = chunks(stream);
+
+	let report = verify_stream(&stream[..]).unwrap();
+	assert!(report.is_ok());
+	assert_eq!(report.chunks, layout.len() as u64);
+	assert_eq!(report.compressed_chunks + report.uncompressed_chunks, layout.len() as u64 - 1);
+	assert_eq!(report.skipped_chunks, 0);
+	assert_eq!(report.compressed_bytes, stream.len() as u64);
+	assert_eq!(report.uncompressed_bytes, data.len() as u64);
+	assert_eq!(report.bad_chunk_offset, None);
+
+	// A bad checksum in the first data chunk, which follows the 10 byte
+	// stream identifier.
+	let mut corrupt = stream.to_vec();
+	corrupt[14] ^= 0x01;
+	let report = verify_stream(&corrupt[..]).unwrap();
+	assert!(!report.is_ok());
+	assert_eq!(report.bad_chunk_offset, Some(10));
+	assert_eq!(report.chunks, 1);
+	assert_eq!(report.uncompressed_bytes, 0);
+	match report.error {
+		Some(Error::ChecksumMismatch { chunk_index: 1, .. }) => {},
+		ref e => panic!("Unexpected Error: {:?}", e),
+	}
+
+	// A stream cut short in its last chunk.
+	let last = stream.len() - layout.last().unwrap().1 - 4;
+	let report = verify_stream(&stream[..stream.len() - 1]).unwrap();
+	assert_eq!(report.bad_chunk_offset, Some(last as u64));
+	assert_eq!(report.chunks, layout.len() as u64 - 1);
+	assert_eq!(report.error, Some(Error::UnexpectedEof { offset: last as u64 }));
+
+	// Padding is counted, and errors reading the stream are returned.
+	let mut padded = stream.to_vec();
+	padded.extend_from_slice(&[0xfe, 0x02, 0x00, 0x00, 0x00, 0x00]);
+	let report = verify_stream(&padded[..]).unwrap();
+	assert!(report.is_ok());
+	assert_eq!(report.skipped_chunks, 1);
+	assert_eq!(report.compressed_bytes, padded.len() as u64);
+	assert_eq!(verify_stream(Broken).unwrap_err().kind(), ErrorKind::BrokenPipe);
+}
+
+#[test]
+/// Snappy: Inspect the Chunks of a Stream
+fn should_inspect_streams() {
+	let data = include_bytes!("data/alice29.txt");
+	let stream = include_bytes!("data/alice29.txt.sz");
+	let layout = chunks(stream);
+
+	let infos: Vec<_> = inspect_stream(&stream[..]).collect::<Result<_>>().unwrap();
+	assert_eq!(infos.len(), layout.len());
+	assert_eq!(infos[0].type_name(), "STREAM_IDENTIFIER");
+	let mut offset = 0;
+	for (i, (info, &(chunk_type, len))) in infos.iter().zip(&layout).enumerate() {
+		assert_eq!((info.index, info.offset, info.chunk_type, info.len), (i as u64, offset, chunk_type, len));
+		assert_eq!(info.error, None);
+		assert_eq!(info.stored_checksum, info.actual_checksum);
+		assert_eq!(info.stored_checksum.is_some(), i > 0);
+		offset += 4 + len as u64;
+	}
+	let decoded: usize = infos.iter().filter_map(|info| info.uncompressed_len).sum();
+	assert_eq!(decoded, data.len());
+
+	// A bad checksum is reported, and the walk goes on past it.
+	let mut corrupt = stream.to_vec();
+	corrupt[14] ^= 0x01;
+	let infos: Vec<_> = inspect_stream(&corrupt[..]).collect::<Result<_>>().unwrap();
+	assert_eq!(infos.len(), layout.len());
+	assert_ne!(infos[1].stored_checksum, infos[1].actual_checksum);
+	match infos[1].error {
+		Some(Error::ChecksumMismatch { chunk_index: 1, .. }) => {},
+		ref e => panic!("Unexpected Error: {:?}", e),
+	}
+	assert!(infos[2..].iter().all(|info| info.error.is_none()));
+
+	// Reserved chunks are named, and a stream cut short ends the walk.
+	let mut padded = stream.to_vec();
+	padded.extend_from_slice(&[0xfe, 0x00, 0x00, 0x00, 0x02, 0x01, 0x00, 0x00, 0x00]);
+	let infos: Vec<_> = inspect_stream(&padded[..]).collect::<Result<_>>().unwrap();
+	let tail: Vec<_> = infos[layout.len()..].iter().map(|info| info.type_name()).collect();
+	assert_eq!(tail, ["PADDING", "RESERVED_UNSKIPPABLE"]);
+	assert_eq!(infos.last().unwrap().error, Some(Error::UnsupportedChunk(0x02)));
+	let res: Result<Vec<_>> = inspect_stream(&stream[..stream.len() - 1]).collect();
+	assert_eq!(res.unwrap_err().kind(), ErrorKind::UnexpectedEof);
+
+	// Chunks are judged as the Decompressor judges them: a stream must start
+	// with its identifier, even if the first chunk is skippable.
+	for &first in &[0xfe, 0xf0, 0xb2] {
+		let mut bad = stream.to_vec();
+		bad[0] = first;
+		let infos: Vec<_> = inspect_stream(&bad[..]).collect::<Result<_>>().unwrap();
+		assert_eq!(infos.len(), layout.len());
+		assert_eq!(infos[0].error, Some(Error::Corrupt { offset: 0 }));
+		assert_eq!(verify_stream(&bad[..]).unwrap().error, Some(Error::Corrupt { offset: 0 }));
+	}
+
+	// A chunk longer than any valid one is refused, but walked past.
+	let mut long = stream[..10].to_vec();
+	let len = 4 + 76_491;
+	long.extend_from_slice(&[0x00, len as u8, (len >> 8) as u8, (len >> 16) as u8]);
+	long.resize(long.len() + len, 0);
+	long.extend_from_slice(&stream[10..]);
+	let infos: Vec<_> = inspect_stream(&long[..]).collect::<Result<_>>().unwrap();
+	assert_eq!(infos.len(), layout.len() + 1);
+	assert_eq!(infos[1].error, Some(Error::Corrupt { offset: 10 }));
+	assert!(infos[2..].iter().all(|info| info.error.is_none()));
+	assert_eq!(verify_stream(&long[..]).unwrap().error, Some(Error::Corrupt { offset: 10 }));
+}
+
+// Cuts data into slices of the given lengths, repeated, with an empty slice
+// after every few.
+fn scatter<'a>(data: &'a [u8], lens: &[usize]) -> Vec<IoSlice<'a>> {
+	let mut slices = Vec::new();
+	let mut rest = data;
+	for (i, &len) in lens.iter().cycle().enumerate() {
+		if rest.is_empty() {
+			break;
+		}
+		let (slice, tail) = rest.split_at(std::cmp::min(len, rest.len()));
+		slices.push(IoSlice::new(slice));
+		if i % 3 == 0 {
+			slices.push(IoSlice::new(&[]));
+		}
+		rest = tail;
+	}
+	slices
+}
+
+#[test]
+/// Snappy: Chunk Vectored Writes as One Input
+fn should_write_vectored() {
+	let data = include_bytes!("data/alice29.txt");
+
+	let mut c = Compressor::new(Vec::new());
+	c.write_all(data).unwrap();
+	let expected = c.finish().unwrap();
+
+	for lens in [&[100, 5000, 1][..], &[70_000, 3], &[65_536]].iter() {
+		let slices = scatter(data, lens);
+		let mut c = Compressor::new(Vec::new());
+		assert_eq!(c.write_vectored(&slices).unwrap(), data.len());
+		assert_eq!(c.finish().unwrap(), expected, "Mismatch for: {:?}", lens);
+	}
+}
+
+#[test]
+/// Snappy: Compress Scattered Input without Concatenating It
+fn should_compress_iovec() {
+	let text = include_bytes!("data/alice29.txt");
+	let random = noise(100_000);
+	let mut repeated = noise(1000);
+	repeated.extend_from_within(..);
+	repeated.extend_from_within(..);
+
+	for data in [&text[..], &random[..], &repeated[..], b"abc", b"", &text[..5]].iter() {
+		let block = compress_to_vec(data);
+		for lens in [&[1][..], &[3, 1000, 7], &[4096], &[1_000_000]].iter() {
+			let slices = scatter(data, lens);
+			let mut dst = vec![0; max_compressed_len(data.len())];
+			let n = compress_iovec(&mut dst, &slices).unwrap();
+			assert_eq!(&dst[..n], &block[..], "Mismatch for: {:?}", lens);
+		}
+	}
+
+	let slices = [IoSlice::new(b"abc"), IoSlice::new(b"def")];
+	assert_eq!(compress_iovec(&mut [0; 4], &slices), Err(Error::BufferTooSmall { needed: max_compressed_len(6) }));
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+/// Snappy: Roundtrip Through Tokio Duplex Streams
+async fn should_do_tokio_duplex() {
+	use tokio::io::{AsyncReadExt, AsyncWriteExt};
+	use snappy_rust::{AsyncCompressor, AsyncDecompressor};
+
+	let data = include_bytes!("data/alice29.txt");
+	// A small duplex buffer forces partial writes and short reads.
+	let (client, server) = tokio::io::duplex(1000);
+
+	let writer = tokio::spawn(async move {
+		let mut c = AsyncCompressor::new(client);
+		for piece in data.chunks(7919) {
+			c.write_all(piece).await.unwrap();
+		}
+		c.shutdown().await.unwrap();
+	});
+
+	let mut out = Vec::new();
+	AsyncDecompressor::new(server).read_to_end(&mut out).await.unwrap();
+	writer.await.unwrap();
+	assert_eq!(&out[..], &data[..]);
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+/// Snappy: Write the Same Stream as the Blocking Compressor
+async fn should_match_blocking_streams() {
+	use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+	use snappy_rust::{AsyncCompressor, AsyncDecompressor};
+
+	let data = noise(200_000);
+	let mut c = Compressor::new(Vec::new());
+	c.write_all(&data[..1000]).unwrap();
+	c.flush().unwrap();
+	c.write_all(&data[1000..]).unwrap();
+	let expected = c.finish().unwrap();
+
+	let mut c = AsyncCompressor::new(Vec::new());
+	c.write_all(&data[..1000]).await.unwrap();
+	c.flush().await.unwrap();
+	c.write_all(&data[1000..]).await.unwrap();
+	c.shutdown().await.unwrap();
+	assert_eq!(c.into_inner(), expected);
+
+	// The decoded data is handed out a chunk at a time.
+	let mut d = AsyncDecompressor::new(&expected[..]);
+	let mut lens = Vec::new();
+	loop {
+		let n = d.fill_buf().await.unwrap().len();
+		if n == 0 {
+			break;
+		}
+		lens.push(n);
+		d.consume(n);
+	}
+	assert_eq!(lens, vec![1000, 65536, 65536, 65536, 2392]);
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+/// Snappy: Report Bad Streams from the Tokio Decompressor
+async fn should_report_tokio_errors() {
+	use tokio::io::AsyncReadExt;
+	use snappy_rust::AsyncDecompressor;
+
+	let stream = include_bytes!("data/alice29.txt.sz");
+	let mut out = Vec::new();
+
+	let mut corrupt = stream.to_vec();
+	corrupt[14] ^= 0x01;
+	let err = AsyncDecompressor::new(&corrupt[..]).read_to_end(&mut out).await.unwrap_err();
+	assert_eq!(err.kind(), ErrorKind::InvalidData);
+	match err.get_ref().and_then(|e| e.downcast_ref::<Error>()) {
+		Some(&Error::ChecksumMismatch { chunk_index: 1, .. }) => {},
+		e => panic!("Unexpected Error: {:?}", e),
+	}
+
+	let err = AsyncDecompressor::new(&stream[..stream.len() - 1]).read_to_end(&mut out).await.unwrap_err();
+	assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+
+	out.clear();
+	let err = AsyncDecompressor::with_limit(&stream[..], 100_000).read_to_end(&mut out).await.unwrap_err();
+	assert_eq!(err.get_ref().and_then(|e| e.downcast_ref::<Error>()), Some(&Error::LimitExceeded { limit: 100_000 }));
+	assert_eq!(out.len(), 65536);
+}
+
+#[cfg(feature = "futures")]
+#[test]
+/// Snappy: Roundtrip Through the Futures Io Traits
+fn should_do_futures_io() {
+	use futures::executor::block_on;
+	use futures::io::{AsyncReadExt, AsyncWriteExt, Cursor};
+	use snappy_rust::{AsyncCompressor, AsyncDecompressor};
 
+	let data = include_bytes!("data/alice29.txt");
+	block_on(async {
+		let mut c = AsyncCompressor::new(Cursor::new(Vec::new()));
+		for piece in data.chunks(7919) {
+			c.write_all(piece).await.unwrap();
+		}
+		c.close().await.unwrap();
+		let stream = c.into_inner().into_inner();
 
+		let mut out = Vec::new();
+		Decompressor::new(&stream[..]).read_to_end(&mut out).unwrap();
+		assert_eq!(&out[..], &data[..]);
 
+		out.clear();
+		AsyncDecompressor::new(&stream[..]).read_to_end(&mut out).await.unwrap();
+		assert_eq!(&out[..], &data[..]);
+	});
+}
+
+#[cfg(feature = "tokio-util")]
+#[tokio::test]
+/// Snappy: Exchange Messages Through Framed
+async fn should_do_framed_codec() {
+	use bytes::Bytes;
+	use futures::{SinkExt, StreamExt};
+	use snappy_rust::{Payload, SnappyCodec};
+	use tokio_util::codec::Framed;
+
+	let text = include_bytes!("data/alice29.txt");
+	let messages = vec![
+		Bytes::new(),
+		Bytes::from_static(b"hello"),
+		Bytes::from_static(text),
+		Bytes::from(noise(100_000)),
+		Bytes::from_static(&text[..70_000]),
+	];
+
+	for &payload in [Payload::Raw, Payload::Framed].iter() {
+		let (client, server) = tokio::io::duplex(4096);
+		let mut client = Framed::new(client, SnappyCodec::new().payload(payload));
+		let mut server = Framed::new(server, SnappyCodec::new().payload(payload));
+
+		let sent = messages.clone();
+		let writer = tokio::spawn(async move {
+			for msg in sent {
+				client.send(msg).await.unwrap();
+			}
+		});
+		for msg in messages.iter() {
+			let received = server.next().await.unwrap().unwrap();
+			assert_eq!(&received[..], &msg[..], "Mismatch for: {:?}", payload);
+		}
+		writer.await.unwrap();
+		assert!(server.next().await.is_none());
+	}
+}
+
+#[cfg(feature = "tokio-util")]
+#[test]
+/// Snappy: Length-Delimit Payloads and Enforce the Frame Limit
+fn should_limit_codec_frames() {
+	use bytes::BytesMut;
+	use snappy_rust::{Payload, SnappyCodec};
+	use tokio_util::codec::{Decoder as _, Encoder as _};
+
+	let data = include_bytes!("data/alice29.txt");
+	let mut codec = SnappyCodec::new();
+
+	// A raw payload is the block that compress writes.
+	let mut wire = BytesMut::new();
+	codec.encode(&data[..], &mut wire).unwrap();
+	let block = compress_to_vec(data);
+	assert_eq!(&wire[..4], &(block.len() as u32).to_be_bytes());
+	assert_eq!(&wire[4..], &block[..]);
+
+	// Frames are only decoded once they are complete.
+	let mut partial = BytesMut::from(&wire[..wire.len() - 1]);
+	assert_eq!(codec.decode(&mut partial).unwrap(), None);
+
+	// A corrupt payload is an error, but the message after it still decodes.
+	let mut corrupt = wire.clone();
+	corrupt[4] ^= 0x01;
+	codec.encode(&b"after"[..], &mut corrupt).unwrap();
+	assert_eq!(codec.decode(&mut corrupt).unwrap_err().kind(), ErrorKind::InvalidData);
+	assert_eq!(&codec.decode(&mut corrupt).unwrap().unwrap()[..], b"after");
+
+	// Messages over the limit are refused on both sides.
+	let mut small = SnappyCodec::new().payload(Payload::Framed).max_frame_len(1000);
+	let mut dst = BytesMut::new();
+	let err = small.encode(&data[..1001], &mut dst).unwrap_err();
+	assert_eq!(err.get_ref().and_then(|e| e.downcast_ref::<Error>()), Some(&Error::TooLarge));
+	let err = small.encode(&noise(990)[..], &mut dst).unwrap_err();
+	assert_eq!(err.get_ref().and_then(|e| e.downcast_ref::<Error>()), Some(&Error::TooLarge));
+	assert!(dst.is_empty());
+
+	let err = small.decode(&mut wire.clone()).unwrap_err();
+	assert_eq!(err.get_ref().and_then(|e| e.downcast_ref::<Error>()), Some(&Error::LimitExceeded { limit: 1000 }));
+
+	// A small frame which decodes to more than the limit.
+	let mut bomb = BytesMut::new();
+	SnappyCodec::new().payload(Payload::Framed).encode(&[0u8; 5000][..], &mut bomb).unwrap();
+	assert!(bomb.len() < 1000);
+	let err = small.decode(&mut bomb).unwrap_err();
+	assert_eq!(err.get_ref().and_then(|e| e.downcast_ref::<Error>()), Some(&Error::LimitExceeded { limit: 1000 }));
+
+	// A raw header claiming more than its tags could decode to is refused
+	// before anything is allocated for it.
+	let mut bomb = BytesMut::from(&b"\x00\x00\x00\x05\x80\x80\x80\x04\x00"[..]);
+	let err = codec.decode(&mut bomb).unwrap_err();
+	assert_eq!(err.get_ref().and_then(|e| e.downcast_ref::<Error>()), Some(&Error::Corrupt { offset: 5 }));
+	assert!(bomb.is_empty());
+}
+
+#[cfg(feature = "bytes")]
+#[test]
+/// Snappy: Compress from Buf Chains into BufMut
+fn should_compress_bufs() {
+	use bytes::{Buf, BytesMut};
+	use snappy_rust::compress_buf;
 
+	let data = include_bytes!("data/alice29.txt");
+	let block = compress_to_vec(data);
 
+	let mut contiguous = &data[..];
+	let mut dst = BytesMut::new();
+	assert_eq!(compress_buf(&mut contiguous, &mut dst), Ok(block.len()));
+	assert_eq!(&dst[..], &block[..]);
+	assert!(contiguous.is_empty());
 
+	// Matches reach across the chunks of a chain.
+	let mut chain = Buf::chain(&data[..50_000], &data[50_000..100_000]).chain(&data[100_000..]);
+	let mut dst = b"prefix".to_vec();
+	assert_eq!(compress_buf(&mut chain, &mut dst), Ok(block.len()));
+	assert_eq!(&dst[..6], b"prefix");
+	assert_eq!(&dst[6..], &block[..]);
+	assert_eq!(chain.remaining(), 0);
+
+	// The block is written straight into room that dst already has.
+	let mut dst = BytesMut::with_capacity(max_compressed_len(data.len()));
+	let mut chain = Buf::chain(&data[..1000], &data[1000..]);
+	assert_eq!(compress_buf(&mut chain, &mut dst), Ok(block.len()));
+	assert_eq!(&dst[..], &block[..]);
+
+	// A Buf that can't list its chunks is taken a chunk at a time.
+	let mut pieces = Pieces(data.chunks(10_000).collect());
+	let mut dst = Vec::new();
+	assert_eq!(compress_buf(&mut pieces, &mut dst), Ok(block.len()));
+	assert_eq!(&dst[..], &block[..]);
+	assert_eq!(pieces.remaining(), 0);
+
+	// A BufMut without room is an error.
+	let mut small = [0u8; 100];
+	assert_eq!(compress_buf(&mut &data[..], &mut &mut small[..]), Err(Error::BufferTooSmall { needed: block.len() }));
+}
+
+// A Buf of several slices, which only shows the first of them at a time.
+#[cfg(feature = "bytes")]
+struct Pieces<'a>(std::collections::VecDeque<&'a [u8]>);
+
+#[cfg(feature = "bytes")]
+impl <'a> bytes::Buf for Pieces<'a> {
+	fn remaining(&self) -> usize {
+		self.0.iter().map(|piece| piece.len()).sum()
+	}
+
+	fn chunk(&self) -> &[u8] {
+		self.0.front().copied().unwrap_or(&[])
+	}
+
+	fn advance(&mut self, mut cnt: usize) {
+		while cnt > 0 {
+			let front = self.0.front_mut().unwrap();
+			let n = std::cmp::min(cnt, front.len());
+			*front = &front[n..];
+			if front.is_empty() {
+				self.0.pop_front();
+			}
+			cnt -= n;
+		}
+	}
+}
+
+#[cfg(feature = "bytes")]
+#[test]
+/// Snappy: Decompress from Buf Chains into BytesMut
+fn should_decompress_bufs() {
+	use bytes::{Buf, BytesMut};
+	use snappy_rust::decompress_buf;
+
+	let data = include_bytes!("data/alice29.txt");
+	let block = compress_to_vec(data);
+
+	let mut dst = BytesMut::from(&b"prefix"[..]);
+	assert_eq!(decompress_buf(&mut &block[..], &mut dst), Ok(data.len()));
+	assert_eq!(&dst[..6], b"prefix");
+	assert_eq!(&dst[6..], &data[..]);
+
+	let mut chain = Buf::chain(&block[..3], &block[3..1000]).chain(&block[1000..]);
+	let mut dst = BytesMut::new();
+	assert_eq!(decompress_buf(&mut chain, &mut dst), Ok(data.len()));
+	assert_eq!(&dst[..], &data[..]);
+	assert_eq!(chain.remaining(), 0);
+
+	// The options of a Decoder apply, and dst is left as it was on errors.
+	let mut dst = BytesMut::from(&b"prefix"[..]);
+	let decoder = Decoder::new().max_len(1000);
+	assert_eq!(decoder.decompress_buf(&mut &block[..], &mut dst), Err(Error::LimitExceeded { limit: 1000 }));
+	assert!(decompress_buf(&mut &block[..block.len() - 1], &mut dst).is_err());
+	assert_eq!(decompress_buf(&mut &b"\xff\xff\xff\xff\x0f\x00"[..], &mut dst), Err(Error::Corrupt { offset: 6 }));
+	assert_eq!(&dst[..], b"prefix");
+	assert!(dst.capacity() < 1 << 20);
+}
+
+// Runs the snappy binary with args, feeding it stdin, and returns its exit
+// code and standard output.
+fn run_cli(args: &[&str], dir: &std::path::Path, stdin: &[u8]) -> (i32, Vec<u8>) {
+	use std::process::{Command, Stdio};
+
+	let mut child = Command::new(env!("CARGO_BIN_EXE_snappy"))
+		.args(args)
+		.current_dir(dir)
+		.stdin(Stdio::piped())
+		.stdout(Stdio::piped())
+		.stderr(Stdio::null())
+		.spawn()
+		.unwrap();
+	child.stdin.take().unwrap().write_all(stdin).unwrap();
+	let out = child.wait_with_output().unwrap();
+	(out.status.code().unwrap(), out.stdout)
+}
+
+#[test]
+/// Snappy: Compress and Decompress Files from the Command Line
+fn should_run_cli() {
+	use std::fs;
+
+	let data = include_bytes!("data/alice29.txt");
+	let dir = std::env::temp_dir().join(format!("snappy-cli-{}", std::process::id()));
+	fs::create_dir_all(&dir).unwrap();
+	fs::write(dir.join("alice29.txt"), &data[..]).unwrap();
+
+	// Files are replaced by their compressed form, unless kept.
+	assert_eq!(run_cli(&["alice29.txt"], &dir, b"").0, 0);
+	assert!(!dir.join("alice29.txt").exists());
+	assert_eq!(run_cli(&["-dk", "alice29.txt.sz"], &dir, b"").0, 0);
+	assert_eq!(fs::read(dir.join("alice29.txt")).unwrap(), &data[..]);
+	assert!(dir.join("alice29.txt.sz").exists());
+
+	// Existing outputs are only overwritten with -f.
+	assert_eq!(run_cli(&["-d", "alice29.txt.sz"], &dir, b"").0, 2);
+	assert_eq!(run_cli(&["-d", "-f", "alice29.txt.sz"], &dir, b"").0, 0);
+	assert!(!dir.join("alice29.txt.sz").exists());
+
+	// Raw blocks, and standard input to standard output.
+	assert_eq!(run_cli(&["--raw", "-k", "alice29.txt"], &dir, b"").0, 0);
+	assert_eq!(fs::read(dir.join("alice29.txt.rawsnappy")).unwrap(), compress_to_vec(data));
+	let (code, out) = run_cli(&["-d", "--raw"], &dir, include_bytes!("data/alice29.txt.rawsnappy"));
+	assert_eq!((code, &out[..]), (0, &data[..]));
+	let (code, out) = run_cli(&["-d"], &dir, include_bytes!("data/alice29.txt.sz"));
+	assert_eq!((code, &out[..]), (0, &data[..]));
+
+	// Corrupt input, I/O errors and usage errors exit differently.
+	let stream = include_bytes!("data/alice29.txt.sz");
+	assert_eq!(run_cli(&["-d"], &dir, &stream[..stream.len() - 1]).0, 1);
+	assert_eq!(run_cli(&["-d", "--raw", "-"], &dir, b"\xff").0, 1);
+	assert_eq!(run_cli(&["-d", "--raw"], &dir, b"\xff\xff\xff\xff\x0f\x00").0, 1);
+	assert_eq!(run_cli(&["-d", "missing.sz"], &dir, b"").0, 2);
+	assert_eq!(run_cli(&["-d", "alice29.txt"], &dir, b"").0, 3);
+	assert_eq!(run_cli(&["-x"], &dir, b"").0, 3);
+	assert!(!dir.join("missing").exists());
+
+	// Listing the chunks of a stream.
+	let (code, out) = run_cli(&["inspect"], &dir, stream);
+	let out = String::from_utf8(out).unwrap();
+	assert_eq!(code, 0);
+	assert_eq!(out.lines().count(), 1 + chunks(stream).len());
+	assert!(out.lines().nth(1).unwrap().contains("STREAM_IDENTIFIER"));
+	let mut corrupt = stream.to_vec();
+	corrupt[14] ^= 0x01;
+	assert_eq!(run_cli(&["inspect", "-"], &dir, &corrupt).0, 1);
+	corrupt[0] = 0xfe;
+	assert_eq!(run_cli(&["inspect"], &dir, &corrupt).0, 1);
+	assert_eq!(run_cli(&["inspect"], &dir, &stream[..stream.len() - 1]).0, 1);
+	assert_eq!(run_cli(&["inspect", "missing.sz"], &dir, b"").0, 2);
+
+	fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+/// Snappy: List the Tags of a Block
+fn should_list_tokens() {
+	// A run is a one byte literal followed by copies from one byte back, and
+	// a repeat is a copy from where it last occurred.
+	let run = compress_to_vec(&[b'a'; 100]);
+	let list: Vec<_> = tokens(&run).collect::<snappy_rust::Result<_>>().unwrap();
+	assert_eq!(list, [
+		Token::Literal { offset: 2, len: 1 },
+		Token::Copy { tag: CopyTag::Copy2, offset: 1, len: 64 },
+		Token::Copy { tag: CopyTag::Copy2, offset: 1, len: 35 },
+	]);
+	let repeat = compress_to_vec(b"abcdefgh0123abcdefgh4567abcdefgh");
+	let list: Vec<_> = tokens(&repeat).collect::<snappy_rust::Result<_>>().unwrap();
+	assert_eq!(list, [
+		Token::Literal { offset: 2, len: 12 },
+		Token::Copy { tag: CopyTag::Copy1, offset: 12, len: 8 },
+		Token::Literal { offset: 17, len: 4 },
+		Token::Copy { tag: CopyTag::Copy1, offset: 12, len: 8 },
+	]);
+
+	// Replaying the tokens rebuilds the data.
+	let data = include_bytes!("data/alice29.txt");
+	let block = include_bytes!("data/alice29.txt.rawsnappy");
+	let mut out = Vec::new();
+	for token in tokens(block) {
+		match token.unwrap() {
+			Token::Literal { offset, len } => out.extend_from_slice(&block[offset..offset + len]),
+			Token::Copy { offset, len, .. } => for _ in 0..len {
+				out.push(out[out.len() - offset]);
+			},
+		}
+	}
+	assert_eq!(&out[..], &data[..]);
+	assert_eq!(tokens(block).decoded_len(), Some(data.len()));
+
+	// Bad tags, and blocks that don't decode to their length, end in an error.
+	let mut it = tokens(b"\x05\x00\x61\x05\x01");
+	assert_eq!(it.next(), Some(Ok(Token::Literal { offset: 2, len: 1 })));
+	assert_eq!(it.next(), Some(Err(Error::Corrupt { offset: 3 })));
+	assert_eq!(it.next(), None);
+	let mut it = tokens(b"\x05\x00\x61");
+	assert_eq!(it.next(), Some(Ok(Token::Literal { offset: 2, len: 1 })));
+	assert_eq!(it.next(), Some(Err(Error::Corrupt { offset: 3 })));
+	assert_eq!(it.next(), None);
+	assert_eq!(tokens(b"\xff").collect::<Vec<_>>(), [Err(Error::Corrupt { offset: 0 })]);
+	assert_eq!(tokens(b"\xff").decoded_len(), None);
+	let limited = Decoder::new().max_len(10);
+	assert_eq!(limited.tokens(&run).next(), Some(Err(Error::LimitExceeded { limit: 10 })));
+}
+
+#[test]
+/// Snappy: Count What Compressing Did
+fn should_count_compress_stats() {
+	let data = include_bytes!("data/alice29.txt");
+
+	let mut stats = CompressStats::default();
+	let mut block = vec![0; max_compressed_len(data.len())];
+	let n = compress_with_stats(&mut block, data, &mut stats).unwrap();
+	assert_eq!(&block[..n], &compress_to_vec(data)[..]);
+	assert_eq!((stats.bytes_in, stats.bytes_out), (data.len() as u64, n as u64));
+	assert_eq!(stats.literal_bytes + stats.match_bytes, data.len() as u64);
+	assert!(stats.ratio() < 1.0 && stats.hit_rate() > 0.0 && stats.average_match_len() >= 4.0);
+	assert!(stats.matches <= stats.table_hits && stats.table_hits <= stats.table_lookups);
+
+	// The tag counts agree with the tags in the block.
+	let mut counted = CompressStats::default();
+	for token in tokens(&block[..n]) {
+		match token.unwrap() {
+			Token::Literal { .. } => counted.literal_tags += 1,
+			Token::Copy { tag: CopyTag::Copy1, .. } => counted.copy1_tags += 1,
+			Token::Copy { tag: CopyTag::Copy2, .. } => counted.copy2_tags += 1,
+			Token::Copy { tag: CopyTag::Copy4, .. } => counted.copy4_tags += 1,
+		}
+	}
+	assert_eq!(
+		(stats.literal_tags, stats.copy1_tags, stats.copy2_tags, stats.copy4_tags),
+		(counted.literal_tags, counted.copy1_tags, counted.copy2_tags, counted.copy4_tags));
+
+	// Stats add up across calls, and are left alone when compressing fails.
+	let once = stats.clone();
+	compress_with_stats(&mut block, data, &mut stats).unwrap();
+	let mut twice = once.clone();
+	twice.add(&once);
+	assert_eq!(stats, twice);
+	assert!(compress_with_stats(&mut [0; 4], data, &mut stats).is_err());
+	assert_eq!(stats, twice);
+	assert_eq!(CompressStats::default().ratio(), 0.0);
+
+	// A Compressor counts the stream it writes, but not input still staged.
+	let mut c = Compressor::new(Vec::new());
+	c.write_all(data).unwrap();
+	let stats = c.stats().clone();
+	let stream = c.finish().unwrap();
+	let data_chunks = chunks(&stream).len() as u64 - 1;
+	assert_eq!(stats.bytes_in, (data_chunks - 1) * 65536);
+	let mut c = Compressor::new(Vec::new());
+	c.write_all(data).unwrap();
+	c.flush().unwrap();
+	let stats = c.stats().clone();
+	assert_eq!((stats.bytes_in, stats.bytes_out), (data.len() as u64, stream.len() as u64));
+	assert_eq!((stats.chunks, stats.uncompressed_chunks), (data_chunks, 0));
+
+	// Chunks stored uncompressed are counted too.
+	let mut c = Compressor::with_policy(Vec::new(), CompressionPolicy::Never);
+	c.write_all(data).unwrap();
+	c.flush().unwrap();
+	let stats = c.stats().clone();
+	assert_eq!((stats.chunks, stats.uncompressed_chunks), (data_chunks, data_chunks));
+	assert_eq!((stats.table_lookups, stats.matches), (0, 0));
+	assert_eq!(stats.bytes_out, c.finish().unwrap().len() as u64);
+}
+
+// Writes data in pieces of the given lengths, repeated, flushing after every
+// few, then finishes the stream.
+fn write_pieces<W: Write>(mut w: W, data: &[u8], lens: &[usize]) -> W {
+	let mut rest = data;
+	for (i, &len) in lens.iter().cycle().enumerate() {
+		if rest.is_empty() {
+			break;
+		}
+		let (piece, tail) = rest.split_at(std::cmp::min(len, rest.len()));
+		w.write_all(piece).unwrap();
+		if i % 7 == 6 {
+			w.flush().unwrap();
+		}
+		rest = tail;
+	}
+	w.flush().unwrap();
+	w
+}
+
+#[test]
+/// Snappy: Compress Chunks in Parallel, Same as the Compressor
+fn should_compress_in_parallel() {
+	let mut data = Vec::new();
+	for _ in 0..4 {
+		data.extend_from_slice(include_bytes!("data/lcet10.txt"));
+	}
+	data.extend((0..200_000u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8));
+
+	let policies = [CompressionPolicy::default(), CompressionPolicy::Always, CompressionPolicy::Never];
+	for &policy in &policies {
+		for lens in [&[usize::MAX][..], &[65536], &[1000, 70_000, 1], &[200_000]] {
+			let serial = write_pieces(Compressor::with_policy(Vec::new(), policy), &data, lens);
+			let serial_stats = serial.stats().clone();
+			let serial = serial.finish().unwrap();
+			for &threads in &[1, 3, 0] {
+				let parallel = write_pieces(ParallelCompressor::with_threads(Vec::new(), policy, threads), &data, lens);
+				assert_eq!(parallel.stats(), &serial_stats);
+				assert!(parallel.finish().unwrap() == serial, "policy {:?}, lens {:?}, threads {}", policy, lens, threads);
+			}
+		}
+	}
+
+	// Dropping emits the last chunk; an empty stream is empty.
+	let mut out = Vec::new();
+	{
+		let mut c = ParallelCompressor::new(&mut out);
+		c.write_all(&data).unwrap();
+	}
+	let mut decomp = Vec::new();
+	Decompressor::new(&out[..]).read_to_end(&mut decomp).unwrap();
+	assert_eq!(decomp, data);
+	assert!(ParallelCompressor::new(Vec::new()).finish().unwrap().is_empty());
+}
+
+#[test]
+/// Snappy: Retry Chunks the Parallel Compressor Failed to Write
+fn should_retry_parallel_writes() {
+	let data = include_bytes!("data/alice29.txt");
+
+	let mut c = ParallelCompressor::with_threads(Faulty { data: Vec::new(), fail: true }, CompressionPolicy::default(), 2);
+	c.write_all(data).unwrap();
+	let err = c.into_inner().unwrap_err();
+	assert_eq!(err.error().kind(), ErrorKind::Other);
+
+	// Once the writer recovers, no chunk has been lost or repeated.
+	let mut c = err.into_inner();
+	c.get_mut().fail = false;
+	let out = c.finish().unwrap().data;
+
+	let mut serial = Compressor::new(Vec::new());
+	serial.write_all(data).unwrap();
+	let serial = serial.finish().unwrap();
+	assert_eq!(out, serial);
+
+	// A chunk that was partly written when the writer failed is picked up
+	// where it stopped, not written again from the start.
+	let mut c = ParallelCompressor::with_threads(Choppy { data: Vec::new(), calls: 0 }, CompressionPolicy::default(), 2);
+	c.write_all(data).unwrap();
+	let out = loop {
+		match c.into_inner() {
+			Ok(w) => break w.data,
+			Err(err) => {
+				assert_eq!(err.error().kind(), ErrorKind::Other);
+				c = err.into_inner();
+			},
+		}
+	};
+	assert_eq!(out, serial);
+}